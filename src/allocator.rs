@@ -0,0 +1,107 @@
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+pub mod bump;
+pub mod fixed_size_block;
+pub mod linked_list;
+
+pub const HEAP_START: usize = 0x_4444_4444_0000;
+pub const HEAP_SIZE: usize = 100 * 1024;
+
+// The linked list allocator is the default, build with the `bump_allocator`
+// or `fixed_size_block_allocator` feature to swap in one of the others
+#[cfg(feature = "bump_allocator")]
+#[global_allocator]
+static ALLOCATOR: Locked<bump::BumpAllocator> = Locked::new(bump::BumpAllocator::new());
+
+#[cfg(all(
+    feature = "fixed_size_block_allocator",
+    not(feature = "bump_allocator")
+))]
+#[global_allocator]
+static ALLOCATOR: Locked<fixed_size_block::FixedSizeBlockAllocator> =
+    Locked::new(fixed_size_block::FixedSizeBlockAllocator::new());
+
+#[cfg(not(any(feature = "bump_allocator", feature = "fixed_size_block_allocator")))]
+#[global_allocator]
+static ALLOCATOR: Locked<linked_list::LinkedListAllocator> =
+    Locked::new(linked_list::LinkedListAllocator::new());
+
+// GlobalAlloc methods only get `&self`, and the trait can't be implemented
+// on spin::Mutex directly from this crate, hence the wrapper
+pub struct Locked<A> {
+    inner: spin::Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Locked {
+            inner: spin::Mutex::new(inner),
+        }
+    }
+
+    pub fn lock(&self) -> spin::MutexGuard<A> {
+        self.inner.lock()
+    }
+}
+
+// Byte counts for the heap. `used` includes whatever the allocator loses to
+// alignment and bookkeeping, so it can be more than what was asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub used: usize,
+    pub free: usize,
+    pub total: usize,
+    pub allocations: usize,
+}
+
+pub fn stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+pub fn init_heap(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let page_range = {
+        let heap_start = VirtAddr::new(HEAP_START as u64);
+        let heap_end = heap_start + HEAP_SIZE - 1u64;
+        let heap_start_page = Page::containing_address(heap_start);
+        let heap_end_page = Page::containing_address(heap_end);
+        Page::range_inclusive(heap_start_page, heap_end_page)
+    };
+
+    for page in page_range {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+
+    unsafe {
+        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    }
+
+    Ok(())
+}
+
+// Rounds `addr` up to `align`, which has to be a power of two
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+#[test_case]
+fn test_stats() {
+    let before = stats();
+    assert_eq!(before.total, HEAP_SIZE);
+    assert_eq!(before.used + before.free, before.total);
+    let block = alloc::vec![0u8; 1024];
+    let after = stats();
+    assert!(after.used >= before.used + 1024);
+    assert_eq!(after.used - before.used, before.free - after.free);
+    assert_eq!(after.allocations, before.allocations + 1);
+    drop(block);
+    assert_eq!(stats().allocations, before.allocations);
+}
@@ -0,0 +1,101 @@
+use super::{align_up, HeapStats, Locked};
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+// Hands out memory by moving `next` forward and never frees individual
+// allocations. The whole heap only becomes reusable again once every
+// allocation has been freed
+pub struct BumpAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    next: usize,
+    allocations: usize,
+}
+
+impl BumpAllocator {
+    pub const fn new() -> Self {
+        BumpAllocator {
+            heap_start: 0,
+            heap_end: 0,
+            next: 0,
+            allocations: 0,
+        }
+    }
+
+    /// # Safety
+    /// The heap range has to be valid, unused memory, and this must only be
+    /// called once
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.next = heap_start;
+    }
+
+    // Freed memory only counts as free again once everything was freed
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            used: self.next - self.heap_start,
+            free: self.heap_end - self.next,
+            total: self.heap_end - self.heap_start,
+            allocations: self.allocations,
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<BumpAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut bump = self.lock();
+
+        let alloc_start = align_up(bump.next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) => end,
+            None => return ptr::null_mut(),
+        };
+
+        if alloc_end > bump.heap_end {
+            ptr::null_mut()
+        } else {
+            bump.next = alloc_end;
+            bump.allocations += 1;
+            alloc_start as *mut u8
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        let mut bump = self.lock();
+
+        bump.allocations -= 1;
+        if bump.allocations == 0 {
+            bump.next = bump.heap_start;
+        }
+    }
+}
+
+#[test_case]
+fn test_bump_upward() {
+    let mut region = [0u64; 64];
+    let allocator = Locked::new(BumpAllocator::new());
+    unsafe { allocator.lock().init(region.as_mut_ptr() as usize, 64 * 8) };
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    let first = unsafe { allocator.alloc(layout) } as usize;
+    let second = unsafe { allocator.alloc(layout) } as usize;
+    assert_eq!(first, region.as_ptr() as usize);
+    assert_eq!(second, first + 16);
+}
+
+#[test_case]
+fn test_bump_reuse() {
+    let mut region = [0u64; 64];
+    let allocator = Locked::new(BumpAllocator::new());
+    unsafe { allocator.lock().init(region.as_mut_ptr() as usize, 64 * 8) };
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    let second = unsafe { allocator.alloc(layout) };
+    // The heap is full now
+    assert!(unsafe { allocator.alloc(layout) }.is_null());
+    unsafe {
+        allocator.dealloc(first, layout);
+        allocator.dealloc(second, layout);
+    }
+    assert_eq!(unsafe { allocator.alloc(layout) }, first);
+}
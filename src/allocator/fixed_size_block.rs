@@ -0,0 +1,172 @@
+use super::linked_list::LinkedListAllocator;
+use super::{HeapStats, Locked};
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::mem;
+
+// Block sizes double each step, and each block is aligned to its own size,
+// so they also have to be powers of two
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+// Keeps a free list per block size. Freed blocks go back onto their list and
+// are handed out again before any new memory is taken. Anything bigger than
+// the largest block size goes to the linked list allocator
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback_allocator: LinkedListAllocator,
+    allocations: usize,
+}
+
+impl FixedSizeBlockAllocator {
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback_allocator: LinkedListAllocator::new(),
+            allocations: 0,
+        }
+    }
+
+    /// # Safety
+    /// The heap range has to be valid, unused memory, and this must only be
+    /// called once
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback_allocator.init(heap_start, heap_size);
+    }
+
+    // Blocks sitting on the free lists are still allocated as far as the
+    // fallback is concerned, so they're moved back to free here
+    pub fn stats(&self) -> HeapStats {
+        let fallback = self.fallback_allocator.stats();
+        let mut cached = 0;
+        for (head, &block_size) in self.list_heads.iter().zip(BLOCK_SIZES) {
+            let mut current = head.as_deref();
+            while let Some(node) = current {
+                cached += block_size;
+                current = node.next.as_deref();
+            }
+        }
+        HeapStats {
+            used: fallback.used - cached,
+            free: fallback.free + cached,
+            total: fallback.total,
+            allocations: self.allocations,
+        }
+    }
+}
+
+// Index of the smallest block size that fits the layout, None if it's
+// too big (or too strictly aligned) for any of them
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+}
+
+unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+        let ptr = match list_index(&layout) {
+            Some(index) => match allocator.list_heads[index].take() {
+                Some(node) => {
+                    allocator.list_heads[index] = node.next.take();
+                    node as *mut ListNode as *mut u8
+                }
+                None => {
+                    // The list is empty, carve a new block out of the fallback
+                    let block_size = BLOCK_SIZES[index];
+                    let layout = Layout::from_size_align(block_size, block_size).unwrap();
+                    allocator.fallback_allocator.allocate(layout)
+                }
+            },
+            None => allocator.fallback_allocator.allocate(layout),
+        };
+        if !ptr.is_null() {
+            allocator.allocations += 1;
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+        allocator.allocations -= 1;
+        match list_index(&layout) {
+            Some(index) => {
+                let new_node = ListNode {
+                    next: allocator.list_heads[index].take(),
+                };
+                // Every block has to be able to hold a node
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                let new_node_ptr = ptr as *mut ListNode;
+                new_node_ptr.write(new_node);
+                allocator.list_heads[index] = Some(&mut *new_node_ptr);
+            }
+            None => {
+                allocator.fallback_allocator.deallocate(ptr, layout);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_allocator(region: &mut [u64]) -> Locked<FixedSizeBlockAllocator> {
+    let allocator = Locked::new(FixedSizeBlockAllocator::new());
+    unsafe {
+        allocator
+            .lock()
+            .init(region.as_mut_ptr() as usize, region.len() * 8)
+    };
+    allocator
+}
+
+#[test_case]
+fn test_fixed_size_block_reuse() {
+    let mut region = [0u64; 512];
+    let allocator = test_allocator(&mut region);
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    assert!(!first.is_null());
+    unsafe { allocator.dealloc(first, layout) };
+    // Far more allocations than fit in the region, only works if the freed
+    // block keeps coming back from the free list
+    for _ in 0..5000 {
+        let block = unsafe { allocator.alloc(layout) };
+        assert_eq!(block, first);
+        unsafe { allocator.dealloc(block, layout) };
+    }
+}
+
+#[test_case]
+fn test_fixed_size_block_lifo() {
+    let mut region = [0u64; 512];
+    let allocator = test_allocator(&mut region);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut blocks = [core::ptr::null_mut(); 16];
+    for block in blocks.iter_mut() {
+        *block = unsafe { allocator.alloc(layout) };
+        assert!(!block.is_null());
+        assert_eq!(*block as usize % 64, 0);
+    }
+    for block in blocks {
+        unsafe { allocator.dealloc(block, layout) };
+    }
+    // Free lists are LIFO, so the blocks come back in reverse order
+    for block in blocks.iter().rev() {
+        assert_eq!(unsafe { allocator.alloc(layout) }, *block);
+    }
+}
+
+#[test_case]
+fn test_fixed_size_block_fallback() {
+    let mut region = [0u64; 512];
+    let allocator = test_allocator(&mut region);
+    let layout = Layout::from_size_align(3000, 8).unwrap();
+    let block = unsafe { allocator.alloc(layout) };
+    assert!(!block.is_null());
+    unsafe { allocator.dealloc(block, layout) };
+    // The fallback got the memory back, so the same region is reused
+    assert_eq!(unsafe { allocator.alloc(layout) }, block);
+}
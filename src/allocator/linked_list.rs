@@ -0,0 +1,170 @@
+use super::{align_up, HeapStats, Locked};
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::{mem, ptr};
+
+// Free regions are tracked in a list of nodes stored inside the free memory
+// itself, so every region has to be at least big enough to hold a node
+struct ListNode {
+    size: usize,
+    next: Option<&'static mut ListNode>,
+}
+
+impl ListNode {
+    const fn new(size: usize) -> Self {
+        ListNode { size, next: None }
+    }
+
+    fn start_addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+pub struct LinkedListAllocator {
+    head: ListNode,
+    size: usize,
+    allocations: usize,
+}
+
+impl LinkedListAllocator {
+    pub const fn new() -> Self {
+        Self {
+            head: ListNode::new(0),
+            size: 0,
+            allocations: 0,
+        }
+    }
+
+    /// # Safety
+    /// The heap range has to be valid, unused memory, and this must only be
+    /// called once
+    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.size = heap_size;
+        self.add_free_region(heap_start, heap_size);
+    }
+
+    // Walks the whole free list, so this takes time proportional to how
+    // fragmented the heap is
+    pub fn stats(&self) -> HeapStats {
+        let mut free = 0;
+        let mut current = &self.head;
+        while let Some(ref region) = current.next {
+            free += region.size;
+            current = region;
+        }
+        HeapStats {
+            used: self.size - free,
+            free,
+            total: self.size,
+            allocations: self.allocations,
+        }
+    }
+
+    // Pushes a region onto the front of the free list
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
+        assert!(size >= mem::size_of::<ListNode>());
+
+        let mut node = ListNode::new(size);
+        node.next = self.head.next.take();
+        let node_ptr = addr as *mut ListNode;
+        node_ptr.write(node);
+        self.head.next = Some(&mut *node_ptr)
+    }
+
+    // Finds a free region that fits and unlinks it from the list, returning
+    // it together with the aligned start address of the allocation
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let mut current = &mut self.head;
+        while let Some(ref mut region) = current.next {
+            if let Ok(alloc_start) = Self::alloc_from_region(region, size, align) {
+                let next = region.next.take();
+                let ret = Some((current.next.take().unwrap(), alloc_start));
+                current.next = next;
+                return ret;
+            } else {
+                current = current.next.as_mut().unwrap();
+            }
+        }
+        None
+    }
+
+    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Result<usize, ()> {
+        let alloc_start = align_up(region.start_addr(), align);
+        let alloc_end = alloc_start.checked_add(size).ok_or(())?;
+
+        if alloc_end > region.end_addr() {
+            return Err(());
+        }
+
+        // Whatever is left over has to be able to hold a node of its own
+        let excess_size = region.end_addr() - alloc_end;
+        if excess_size > 0 && excess_size < mem::size_of::<ListNode>() {
+            return Err(());
+        }
+
+        Ok(alloc_start)
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::size_align(layout);
+
+        if let Some((region, alloc_start)) = self.find_region(size, align) {
+            let alloc_end = alloc_start.checked_add(size).expect("overflow");
+            let excess_size = region.end_addr() - alloc_end;
+            if excess_size > 0 {
+                unsafe { self.add_free_region(alloc_end, excess_size) };
+            }
+            self.allocations += 1;
+            alloc_start as *mut u8
+        } else {
+            ptr::null_mut()
+        }
+    }
+
+    /// # Safety
+    /// `ptr` has to come from `allocate` on this allocator with the same layout
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        self.allocations -= 1;
+        self.add_free_region(ptr as usize, size)
+    }
+
+    // Adjusts a layout so the region it produces can hold a ListNode once freed
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(mem::align_of::<ListNode>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        let size = layout.size().max(mem::size_of::<ListNode>());
+        (size, layout.align())
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.lock().allocate(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.lock().deallocate(ptr, layout)
+    }
+}
+
+#[test_case]
+fn test_linked_list_stats() {
+    let mut region = [0u64; 64];
+    let allocator = Locked::new(LinkedListAllocator::new());
+    unsafe { allocator.lock().init(region.as_mut_ptr() as usize, 64 * 8) };
+    let empty = allocator.lock().stats();
+    assert_eq!((empty.used, empty.free, empty.total), (0, 512, 512));
+    let layout = Layout::from_size_align(128, 8).unwrap();
+    let block = unsafe { allocator.alloc(layout) };
+    let stats = allocator.lock().stats();
+    assert_eq!((stats.used, stats.free, stats.allocations), (128, 384, 1));
+    unsafe { allocator.dealloc(block, layout) };
+    assert_eq!(allocator.lock().stats(), empty);
+}
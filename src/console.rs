@@ -0,0 +1,93 @@
+use crate::vga_buffer::Color;
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+// Somewhere text can be shown. The VGA writer and the serial port both
+// implement it, so the shell can run its commands on either one
+pub trait Console: fmt::Write {
+    fn clear(&mut self);
+    fn set_color(&mut self, foreground: Color, background: Color);
+
+    // Writes in another foreground color and then goes back to the current
+    // one. Consoles without colors just write the text
+    fn write_colored(&mut self, _foreground: Color, args: fmt::Arguments) -> fmt::Result {
+        self.write_fmt(args)
+    }
+}
+
+pub type SharedConsole = &'static Mutex<dyn Console + Send>;
+
+// Where print!, cprint! and clear! go. None means the default, which is
+// the VGA writer, or COM1 without the vga_text feature. The default keeps
+// its special cases, like holding output back while a line is being read
+static ACTIVE: Mutex<Option<SharedConsole>> = Mutex::new(None);
+
+// For example `console::set_active(&*serial::SERIAL1)` to print to COM1
+pub fn set_active(console: SharedConsole) {
+    interrupts::without_interrupts(|| *ACTIVE.lock() = Some(console));
+}
+
+pub fn reset_active() {
+    interrupts::without_interrupts(|| *ACTIVE.lock() = None);
+}
+
+pub fn active() -> Option<SharedConsole> {
+    interrupts::without_interrupts(|| *ACTIVE.lock())
+}
+
+// Keeps everything written to it, for tests that need a console to check
+// the output of. Clearing drops what was written so far
+#[cfg(test)]
+#[derive(Default)]
+pub struct Recorder {
+    pub output: alloc::string::String,
+    pub cleared: bool,
+}
+
+#[cfg(test)]
+impl Recorder {
+    pub const fn new() -> Recorder {
+        Recorder {
+            output: alloc::string::String::new(),
+            cleared: false,
+        }
+    }
+}
+
+#[cfg(test)]
+impl fmt::Write for Recorder {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.output.push_str(s);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Console for Recorder {
+    fn clear(&mut self) {
+        self.output.clear();
+        self.cleared = true;
+    }
+    fn set_color(&mut self, _foreground: Color, _background: Color) {}
+}
+
+#[test_case]
+fn test_set_active() {
+    use crate::vga_buffer::WRITER;
+    use crate::{clear, cprint, print};
+
+    static RECORDER: Mutex<Recorder> = Mutex::new(Recorder::new());
+    let screen = interrupts::without_interrupts(|| WRITER.lock().snapshot());
+    set_active(&RECORDER);
+    print!("lost");
+    clear!();
+    print!("captured {}", 1);
+    cprint!(Color::Red, " and {}", 2);
+    reset_active();
+    let recorder = RECORDER.lock();
+    assert!(recorder.cleared);
+    assert_eq!(recorder.output, "captured 1 and 2");
+    // None of it reached the screen
+    interrupts::without_interrupts(|| assert!(WRITER.lock().snapshot() == screen));
+}
@@ -0,0 +1,122 @@
+use core::arch::x86_64::{__cpuid_count, CpuidResult};
+use heapless::{String, Vec};
+
+const LEAF_VENDOR: u32 = 0x0;
+const LEAF_FEATURES: u32 = 0x1;
+const LEAF_EXTENDED_FEATURES: u32 = 0x7;
+const LEAF_EXTENDED_MAX: u32 = 0x8000_0000;
+const LEAF_BRAND: [u32; 3] = [0x8000_0002, 0x8000_0003, 0x8000_0004];
+
+#[derive(Debug, Clone, Copy)]
+enum Register {
+    Ecx,
+    Edx,
+    Ebx,
+}
+
+// Name, leaf, register and bit of each reported feature flag
+const FEATURES: &[(&str, u32, Register, u32)] = &[
+    ("tsc", LEAF_FEATURES, Register::Edx, 4),
+    ("apic", LEAF_FEATURES, Register::Edx, 9),
+    ("sse", LEAF_FEATURES, Register::Edx, 25),
+    ("sse2", LEAF_FEATURES, Register::Edx, 26),
+    ("sse3", LEAF_FEATURES, Register::Ecx, 0),
+    ("ssse3", LEAF_FEATURES, Register::Ecx, 9),
+    ("sse4.1", LEAF_FEATURES, Register::Ecx, 19),
+    ("sse4.2", LEAF_FEATURES, Register::Ecx, 20),
+    ("x2apic", LEAF_FEATURES, Register::Ecx, 21),
+    ("avx", LEAF_FEATURES, Register::Ecx, 28),
+    ("rdrand", LEAF_FEATURES, Register::Ecx, 30),
+    ("hypervisor", LEAF_FEATURES, Register::Ecx, 31),
+    ("avx2", LEAF_EXTENDED_FEATURES, Register::Ebx, 5),
+    ("rdseed", LEAF_EXTENDED_FEATURES, Register::Ebx, 18),
+];
+
+#[derive(Debug, Clone)]
+pub struct CpuInfo {
+    pub vendor: String<12>,
+    // None on CPUs without the extended brand string leaves
+    pub brand: Option<String<48>>,
+    pub features: Vec<&'static str, { FEATURES.len() }>,
+}
+
+impl CpuInfo {
+    pub fn has(&self, feature: &str) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+fn cpuid(leaf: u32) -> CpuidResult {
+    // Every x86_64 CPU has cpuid, callers check the leaf is supported
+    unsafe { __cpuid_count(leaf, 0) }
+}
+
+// Pushes the bytes of each register in order, they're little endian ASCII
+fn push_registers<const N: usize>(string: &mut String<N>, registers: &[u32]) {
+    for register in registers {
+        for byte in register.to_le_bytes() {
+            // The brand string is padded out with NULs
+            if byte != 0 {
+                let _ = string.push(char::from(byte));
+            }
+        }
+    }
+}
+
+pub fn info() -> CpuInfo {
+    let vendor_leaf = cpuid(LEAF_VENDOR);
+    let max_leaf = vendor_leaf.eax;
+    let mut vendor = String::new();
+    // The vendor string is spread over ebx, edx, ecx, in that order
+    push_registers(
+        &mut vendor,
+        &[vendor_leaf.ebx, vendor_leaf.edx, vendor_leaf.ecx],
+    );
+
+    let brand = if cpuid(LEAF_EXTENDED_MAX).eax >= LEAF_BRAND[2] {
+        let mut brand: String<48> = String::new();
+        for leaf in LEAF_BRAND {
+            let result = cpuid(leaf);
+            push_registers(
+                &mut brand,
+                &[result.eax, result.ebx, result.ecx, result.edx],
+            );
+        }
+        // Some CPUs right align the brand string with leading spaces
+        Some(String::from(brand.trim()))
+    } else {
+        None
+    };
+
+    let mut features = Vec::new();
+    for &(name, leaf, register, bit) in FEATURES {
+        if leaf > max_leaf {
+            continue;
+        }
+        let result = cpuid(leaf);
+        let value = match register {
+            Register::Ecx => result.ecx,
+            Register::Edx => result.edx,
+            Register::Ebx => result.ebx,
+        };
+        if value & (1 << bit) != 0 {
+            // There is room for every entry in FEATURES
+            let _ = features.push(name);
+        }
+    }
+
+    CpuInfo {
+        vendor,
+        brand,
+        features,
+    }
+}
+
+#[test_case]
+fn test_vendor() {
+    let info = info();
+    let known = ["GenuineIntel", "AuthenticAMD", "TCGTCGTCGTCG"];
+    assert!(known.contains(&info.vendor.as_str()));
+    // Long mode needs both of these, so any x86_64 CPU reports them
+    assert!(info.has("sse") && info.has("sse2"));
+}
@@ -0,0 +1,105 @@
+use crate::println;
+use core::fmt::Write;
+use heapless::String;
+
+// Like std's dbg!, but over serial so it doesn't mess up the screen. The
+// value is moved through and handed back, so `let x = kdbg!(compute());`
+// still only calls compute once
+#[macro_export]
+macro_rules! kdbg {
+    () => {
+        $crate::serial_println!("[{}:{}]", file!(), line!())
+    };
+    ($val:expr $(,)?) => {
+        match $val {
+            tmp => {
+                $crate::serial_println!(
+                    "[{}:{}] {} = {:#?}",
+                    file!(),
+                    line!(),
+                    stringify!($val),
+                    &tmp
+                );
+                tmp
+            }
+        }
+    };
+    ($($val:expr),+ $(,)?) => {
+        ($($crate::kdbg!($val)),+,)
+    };
+}
+
+const BYTES_PER_LINE: usize = 16;
+
+// Room for a full 64 bit address, 16 hex bytes and the ASCII gutter
+pub type HexdumpLine = String<96>;
+
+// Formats one line as `0000: 48 65 6C 6C ...  |Hell...|`. A short final
+// chunk is padded so its gutter lines up with the lines above
+pub fn hexdump_line(chunk: &[u8], addr: usize) -> HexdumpLine {
+    let mut line = HexdumpLine::new();
+    write!(line, "{:04X}: ", addr).unwrap();
+    for i in 0..BYTES_PER_LINE {
+        match chunk.get(i) {
+            Some(byte) => write!(line, "{:02X} ", byte).unwrap(),
+            None => line.push_str("   ").unwrap(),
+        }
+    }
+    line.push_str(" |").unwrap();
+    for &byte in chunk {
+        let c = match byte {
+            0x20..=0x7e => char::from(byte),
+            _ => '.',
+        };
+        line.push(c).unwrap();
+    }
+    line.push('|').unwrap();
+    line
+}
+
+pub fn hexdump(bytes: &[u8], base_addr: usize) {
+    for (i, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        println!("{}", hexdump_line(chunk, base_addr + i * BYTES_PER_LINE));
+    }
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_hexdump() {
+    use crate::vga_buffer::WRITER;
+    use x86_64::instructions::interrupts;
+    let bytes = b"Hello, hexdump!\x00\x01\x02\x7f\xff";
+    interrupts::without_interrupts(|| {
+        println!();
+        hexdump(bytes, 0);
+        let writer = WRITER.lock();
+        let first = writer.read_row_trimmed(writer.row_position - 2);
+        assert_eq!(
+            &first[..],
+            b"0000: 48 65 6C 6C 6F 2C 20 68 65 78 64 75 6D 70 21 00  |Hello, hexdump!.|"
+        );
+        let second = writer.read_row_trimmed(writer.row_position - 1);
+        assert!(second.starts_with(b"0010: 01 02 7F FF    "));
+        assert!(second.ends_with(b"  |....|"));
+    })
+}
+
+#[test_case]
+fn test_kdbg() {
+    fn next(calls: &mut u32) -> u32 {
+        *calls += 1;
+        *calls * 21
+    }
+    let mut calls = 0;
+    let output = crate::serial::capture(|| {
+        let value = kdbg!(next(&mut calls));
+        assert_eq!(value, 21);
+    });
+    assert_eq!(calls, 1);
+    assert!(output.starts_with("[src/debug.rs:"), "{:?}", output);
+    assert!(
+        output.ends_with("] next(&mut calls) = 21\n"),
+        "{:?}",
+        output
+    );
+}
@@ -0,0 +1,127 @@
+use crate::interrupts::next_tick;
+use crate::keyboard;
+use crate::rng::{self, Rng};
+#[cfg(test)]
+use crate::vga_buffer::ScreenChar;
+use crate::vga_buffer::{Color, ColorCode, Writer, BUFFER_WIDTH, WRITER};
+use x86_64::instructions::interrupts;
+
+const HEAD_COLOR: ColorCode = ColorCode::new(Color::LightGreen, Color::Black);
+const TRAIL_COLOR: ColorCode = ColorCode::new(Color::Green, Color::Black);
+// Out of 100, how likely an empty column is to start a new drop each frame
+const DROP_CHANCE: u64 = 12;
+
+#[derive(Debug, Clone, Copy)]
+struct Raindrop {
+    // Row the next glyph goes on. It runs past the bottom so the tail can
+    // drain off the screen
+    head: usize,
+    length: usize,
+}
+
+// One falling drop per column at most. The head is drawn bright and turns
+// green as it moves on, and the cell a drop's length behind it is blanked
+pub struct MatrixRain {
+    rng: Rng,
+    drops: [Option<Raindrop>; BUFFER_WIDTH],
+}
+
+impl MatrixRain {
+    pub fn new(seed: u64) -> MatrixRain {
+        MatrixRain {
+            rng: Rng::new(seed),
+            drops: [None; BUFFER_WIDTH],
+        }
+    }
+
+    // Draws one frame, moving every drop down a row
+    pub fn step(&mut self, writer: &mut Writer) {
+        let (width, height) = writer.size();
+        for col in 0..width {
+            let drop = match self.drops[col] {
+                Some(drop) => drop,
+                None if self.rng.next_range(0, 100) < DROP_CHANCE => Raindrop {
+                    head: 0,
+                    length: self.rng.next_range(4, (height as u64 / 2).max(5)) as usize,
+                },
+                None => continue,
+            };
+            if drop.head > 0 && drop.head <= height {
+                let glyph = self.glyph();
+                writer.write_at(
+                    drop.head - 1,
+                    col,
+                    glyph.encode_utf8(&mut [0; 4]),
+                    TRAIL_COLOR,
+                );
+            }
+            if drop.head < height {
+                let glyph = self.glyph();
+                writer.write_at(drop.head, col, glyph.encode_utf8(&mut [0; 4]), HEAD_COLOR);
+            }
+            if let Some(tail) = drop.head.checked_sub(drop.length) {
+                writer.write_at(tail, col, " ", TRAIL_COLOR);
+            }
+            self.drops[col] = match drop.head + 1 {
+                head if head > height + drop.length => None,
+                head => Some(Raindrop { head, ..drop }),
+            };
+        }
+    }
+
+    fn glyph(&mut self) -> char {
+        char::from(self.rng.next_range(0x21, 0x7f) as u8)
+    }
+}
+
+// Rain until a key is pressed, one frame per timer tick, then put the
+// screen back the way it was. Only the cells on screen are drawn over, the
+// scrollback isn't touched
+pub async fn matrix_rain() {
+    let mut rain = MatrixRain::new(rng::seed());
+    let saved = interrupts::without_interrupts(|| WRITER.lock().snapshot());
+    while keyboard::try_read_key().is_none() {
+        interrupts::without_interrupts(|| rain.step(&mut WRITER.lock()));
+        next_tick().await;
+    }
+    interrupts::without_interrupts(|| WRITER.lock().restore(&saved));
+}
+
+#[test_case]
+fn test_matrix_rain() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let saved = writer.snapshot();
+        let cursor = (writer.row_position, writer.column_position);
+        // Start from blank, so every glyph drawn is a visible change
+        writer.clear_screen();
+        let before = writer.snapshot();
+        let mut rain = MatrixRain::new(3);
+        for _ in 0..5 {
+            rain.step(&mut writer);
+        }
+        let after = writer.snapshot();
+        writer.restore(&saved);
+        (writer.row_position, writer.column_position) = cursor;
+        let (width, height) = writer.size();
+        let mut changed = 0;
+        for col in 0..width {
+            match rain.drops[col] {
+                // Five frames is too few for any drop to have finished, so
+                // every drop is still falling and has drawn its head
+                Some(drop) => {
+                    let head = after[drop.head - 1][col];
+                    assert_eq!(head, ScreenChar::new(head.ascii_character, HEAD_COLOR));
+                    assert_ne!(head, before[drop.head - 1][col]);
+                    changed += 1;
+                }
+                None => {
+                    for row in 0..height {
+                        assert_eq!(after[row][col], before[row][col]);
+                    }
+                }
+            }
+        }
+        assert!(changed > 0);
+    });
+}
@@ -0,0 +1,147 @@
+use core::fmt;
+
+const BYTE_UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+#[derive(Debug, Clone, Copy)]
+pub struct HumanBytes(u64);
+
+// Byte counts under 1 KiB are shown as is, anything bigger with the largest
+// unit that keeps it at 1 or more and a single decimal, rounded down
+pub fn human_bytes(bytes: u64) -> HumanBytes {
+    HumanBytes(bytes)
+}
+
+impl fmt::Display for HumanBytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut unit = 0;
+        while unit + 1 < BYTE_UNITS.len() && self.0 >> (10 * (unit + 2)) > 0 {
+            unit += 1;
+        }
+        // Tenths of the unit, in u128 since bytes * 10 can overflow a u64
+        let tenths = (u128::from(self.0) * 10) >> (10 * (unit + 1));
+        write!(f, "{}.{} {}", tenths / 10, tenths % 10, BYTE_UNITS[unit])
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hex(u64);
+
+// 0x prefixed, with an underscore between each group of four digits the way
+// addresses are written in the source, like 0x4444_4444_0000
+pub fn hex(n: u64) -> Hex {
+    Hex(n)
+}
+
+impl fmt::Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let digits = (64 - self.0.leading_zeros() as usize + 3) / 4;
+        f.write_str("0x")?;
+        for digit in (0..digits.max(1)).rev() {
+            write!(f, "{:x}", (self.0 >> (digit * 4)) & 0xF)?;
+            if digit > 0 && digit % 4 == 0 {
+                f.write_str("_")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// For panic paths. These fill a buffer on the stack and hand it over in one
+// write_str, so unlike write! they don't go through the formatting
+// machinery, and nothing here can allocate even if the heap is broken
+pub fn write_u64(writer: &mut impl fmt::Write, mut n: u64) -> fmt::Result {
+    // u64::MAX has 20 digits
+    let mut buffer = [0u8; 20];
+    let mut start = buffer.len();
+    loop {
+        start -= 1;
+        buffer[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            break;
+        }
+    }
+    writer.write_str(core::str::from_utf8(&buffer[start..]).unwrap())
+}
+
+// Like {:#x}, lowercase with no padding or underscores
+pub fn write_hex(writer: &mut impl fmt::Write, mut n: u64) -> fmt::Result {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut buffer = [0u8; 18];
+    let mut start = buffer.len();
+    loop {
+        start -= 1;
+        buffer[start] = DIGITS[(n & 0xF) as usize];
+        n >>= 4;
+        if n == 0 {
+            break;
+        }
+    }
+    start -= 2;
+    buffer[start..start + 2].copy_from_slice(b"0x");
+    writer.write_str(core::str::from_utf8(&buffer[start..]).unwrap())
+}
+
+#[cfg(test)]
+fn format(value: impl fmt::Display) -> heapless::String<32> {
+    use core::fmt::Write;
+    let mut text = heapless::String::new();
+    write!(text, "{}", value).unwrap();
+    text
+}
+
+#[test_case]
+fn test_human_bytes() {
+    assert_eq!(format(human_bytes(0)), "0 B");
+    assert_eq!(format(human_bytes(1023)), "1023 B");
+    assert_eq!(format(human_bytes(1024)), "1.0 KiB");
+    assert_eq!(format(human_bytes(1536)), "1.5 KiB");
+    assert_eq!(format(human_bytes(1048575)), "1023.9 KiB");
+    assert_eq!(format(human_bytes(1048576)), "1.0 MiB");
+    assert_eq!(format(human_bytes((5 << 30) | (1 << 29))), "5.5 GiB");
+    assert_eq!(format(human_bytes(u64::MAX)), "15.9 EiB");
+}
+
+#[test_case]
+fn test_hex() {
+    assert_eq!(format(hex(0)), "0x0");
+    assert_eq!(format(hex(0xb8000)), "0xb_8000");
+    assert_eq!(format(hex(0x4444_4444_0000)), "0x4444_4444_0000");
+    assert_eq!(format(hex(u64::MAX)), "0xffff_ffff_ffff_ffff");
+}
+
+#[cfg(test)]
+fn written(
+    write: fn(&mut heapless::String<32>, u64) -> fmt::Result,
+    n: u64,
+) -> heapless::String<32> {
+    let mut text = heapless::String::new();
+    write(&mut text, n).unwrap();
+    text
+}
+
+#[test_case]
+fn test_write_u64() {
+    assert_eq!(written(write_u64, 0), "0");
+    let mut power = 1;
+    for zeros in 0..20 {
+        let expected = format(format_args!("1{:0<1$}", "", zeros));
+        assert_eq!(written(write_u64, power), expected);
+        power = power.wrapping_mul(10);
+    }
+    assert_eq!(written(write_u64, 9), "9");
+    assert_eq!(written(write_u64, 999_999), "999999");
+    assert_eq!(written(write_u64, u64::MAX), "18446744073709551615");
+}
+
+#[test_case]
+fn test_write_hex() {
+    assert_eq!(written(write_hex, 0), "0x0");
+    assert_eq!(written(write_hex, 0x10), "0x10");
+    assert_eq!(written(write_hex, 0xb8000), "0xb8000");
+    assert_eq!(written(write_hex, 1_000_000), "0xf4240");
+    assert_eq!(written(write_hex, u64::MAX), "0xffffffffffffffff");
+}
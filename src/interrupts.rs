@@ -1,8 +1,14 @@
-use crate::{gdt, print, println};
+use crate::{gdt, hlt_loop, keyboard, println, serial, watchdog};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::Poll;
+use futures_util::future;
+use futures_util::task::AtomicWaker;
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -10,14 +16,39 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+// Woken on every timer tick. It only holds one waker, so only one task at a
+// time can wait in next_tick, a second one would take over from the first
+static TICK_WAKER: AtomicWaker = AtomicWaker::new();
+
+// Resolves on the next timer tick and returns the new tick count
+pub async fn next_tick() -> u64 {
+    let start = ticks();
+    future::poll_fn(|cx| {
+        TICK_WAKER.register(cx.waker());
+        match ticks() {
+            now if now > start => Poll::Ready(now),
+            _ => Poll::Pending,
+        }
+    })
+    .await
+}
+
+#[derive(Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Serial1 = PIC_1_OFFSET + 4,
 }
 
 impl InterruptIndex {
-    fn as_u8(self) -> u8 {
+    pub fn as_u8(self) -> u8 {
         self as u8
     }
     fn as_usize(self) -> usize {
@@ -34,8 +65,13 @@ lazy_static! {
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
+        idt.page_fault.set_handler_fn(page_fault_handler);
+        idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+        idt.general_protection_fault
+            .set_handler_fn(general_protection_fault_handler);
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Serial1.as_usize()].set_handler_fn(serial_interrupt_handler);
         idt
     };
 }
@@ -55,41 +91,109 @@ extern "x86-interrupt" fn double_fault_handler(
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
+// Split out of the handler so tests with their own IDT can check the report
+pub fn print_page_fault(
+    stack_frame: &InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) -> VirtAddr {
+    // CR2 holds the address whose access faulted
+    let address = Cr2::read();
+    println!("EXCEPTION: PAGE FAULT");
+    println!("Accessed Address: {:?}", address);
+    println!("Error Code: {:?}", error_code);
+    println!("{:#?}", stack_frame);
+    address
+}
+
+// There is nothing to fix up a fault with yet, so report it and stop
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    print_page_fault(&stack_frame, error_code);
+    hlt_loop();
+}
+
+// The saved instruction pointer is the start of the bad instruction itself
+pub fn print_invalid_opcode(stack_frame: &InterruptStackFrame) -> VirtAddr {
+    let rip = stack_frame.instruction_pointer;
+    println!("EXCEPTION: INVALID OPCODE at {:?}", rip);
+    println!("{:#?}", stack_frame);
+    rip
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    print_invalid_opcode(&stack_frame);
+    hlt_loop();
+}
+
+// For segment related faults the error code is the offending selector with
+// its RPL bits cleared, otherwise it is 0. To see it fire, load a selector
+// past the end of the GDT from the kernel, e.g.
+//
+//     core::arch::asm!("mov ds, {0:x}", in(reg) 0x1238u16);
+//
+// which should halt with an error code of 0x1238
+extern "x86-interrupt" fn general_protection_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    println!("EXCEPTION: GENERAL PROTECTION FAULT");
+    println!("Error Code: {:#x}", error_code);
+    println!("{:#?}", stack_frame);
+    hlt_loop();
+}
+
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
+    TICK_WAKER.wake();
+    watchdog::check(now);
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-    use spin::Mutex;
     use x86_64::instructions::port::Port;
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(
-            Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore)
-        );
-    }
-    let mut keyboard = KEYBOARD.lock();
     let mut port = Port::new(0x60);
-
     let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::RawKey(key) => print!("{:?}", key),
-            }
-        }
-    }
+    keyboard::add_scancode(scancode);
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
     }
 }
 
+// The UART has a FIFO, so drain it while the line status register reports
+// data ready rather than taking one byte per interrupt
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    use x86_64::instructions::port::Port;
+    let mut data: Port<u8> = Port::new(0x3F8);
+    let mut line_status: Port<u8> = Port::new(0x3FD);
+    while unsafe { line_status.read() } & 1 != 0 {
+        serial::add_byte(unsafe { data.read() });
+    }
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Serial1.as_u8());
+    }
+}
+
 #[test_case]
 fn test_breakpoint_exception() {
     x86_64::instructions::interrupts::int3();
 }
+
+#[test_case]
+fn test_timer_ticks() {
+    let start = ticks();
+    // Any interrupt wakes hlt, so give the timer a few chances to fire
+    for _ in 0..100 {
+        if ticks() > start {
+            break;
+        }
+        x86_64::instructions::hlt();
+    }
+    assert!(ticks() > start);
+}
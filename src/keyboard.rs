@@ -0,0 +1,434 @@
+use crate::print;
+use crate::vga_buffer::{self, CONSOLE_COUNT};
+use conquer_once::spin::OnceCell;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use crossbeam_queue::ArrayQueue;
+use futures_util::stream::{Stream, StreamExt};
+use futures_util::task::AtomicWaker;
+use lazy_static::lazy_static;
+use pc_keyboard::{
+    layouts, DecodeState, DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard,
+    ScancodeSet1, ScancodeSet2,
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+const SCANCODE_QUEUE_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyboardLayout {
+    Us104Key,
+    Uk105Key,
+    Azerty,
+    Dvorak104Key,
+    Jis109Key,
+}
+
+static LAYOUT: Mutex<KeyboardLayout> = Mutex::new(KeyboardLayout::Us104Key);
+
+pub fn set_layout(layout: KeyboardLayout) {
+    *LAYOUT.lock() = layout;
+}
+
+pub fn layout() -> KeyboardLayout {
+    *LAYOUT.lock()
+}
+
+// pc-keyboard picks the layout through a type parameter, so this stands in
+// for it and forwards to whichever layout is currently selected
+pub struct ActiveLayout;
+
+impl pc_keyboard::KeyboardLayout for ActiveLayout {
+    fn map_keycode(
+        keycode: KeyCode,
+        modifiers: &pc_keyboard::Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        match *LAYOUT.lock() {
+            KeyboardLayout::Us104Key => {
+                layouts::Us104Key::map_keycode(keycode, modifiers, handle_ctrl)
+            }
+            KeyboardLayout::Uk105Key => {
+                layouts::Uk105Key::map_keycode(keycode, modifiers, handle_ctrl)
+            }
+            KeyboardLayout::Azerty => layouts::Azerty::map_keycode(keycode, modifiers, handle_ctrl),
+            KeyboardLayout::Dvorak104Key => {
+                layouts::Dvorak104Key::map_keycode(keycode, modifiers, handle_ctrl)
+            }
+            KeyboardLayout::Jis109Key => {
+                layouts::Jis109Key::map_keycode(keycode, modifiers, handle_ctrl)
+            }
+        }
+    }
+}
+
+// QEMU, and PS/2 controllers with translation on (the default), deliver set 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    Set1,
+    Set2,
+}
+
+static SCANCODE_SET: Mutex<ScancodeSet> = Mutex::new(ScancodeSet::Set1);
+
+// Also throws away any half received sequence and the decoder's modifier
+// state, since those are specific to the old set
+pub fn set_scancode_set(set: ScancodeSet) {
+    *SCANCODE_SET.lock() = set;
+    *KEYBOARD.lock() = new_keyboard();
+}
+
+pub fn scancode_set() -> ScancodeSet {
+    *SCANCODE_SET.lock()
+}
+
+// Same idea as ActiveLayout, for the scancode set
+pub struct ActiveScancodeSet;
+
+impl pc_keyboard::ScancodeSet for ActiveScancodeSet {
+    fn advance_state(
+        state: &mut DecodeState,
+        code: u8,
+    ) -> Result<Option<KeyEvent>, pc_keyboard::Error> {
+        match *SCANCODE_SET.lock() {
+            ScancodeSet::Set1 => ScancodeSet1::advance_state(state, code),
+            ScancodeSet::Set2 => ScancodeSet2::advance_state(state, code),
+        }
+    }
+
+    fn map_scancode(code: u8) -> Result<KeyCode, pc_keyboard::Error> {
+        match *SCANCODE_SET.lock() {
+            ScancodeSet::Set1 => ScancodeSet1::map_scancode(code),
+            ScancodeSet::Set2 => ScancodeSet2::map_scancode(code),
+        }
+    }
+
+    fn map_extended_scancode(code: u8) -> Result<KeyCode, pc_keyboard::Error> {
+        match *SCANCODE_SET.lock() {
+            ScancodeSet::Set1 => ScancodeSet1::map_extended_scancode(code),
+            ScancodeSet::Set2 => ScancodeSet2::map_extended_scancode(code),
+        }
+    }
+}
+
+fn new_keyboard() -> Keyboard<ActiveLayout, ActiveScancodeSet> {
+    Keyboard::new(
+        ActiveLayout,
+        ActiveScancodeSet,
+        // Ctrl plus a letter gives the matching control character, so Ctrl+C
+        // comes through as '\u{3}'
+        HandleControl::MapLettersToUnicode,
+    )
+}
+
+lazy_static! {
+    static ref KEYBOARD: Mutex<Keyboard<ActiveLayout, ActiveScancodeSet>> =
+        Mutex::new(new_keyboard());
+}
+
+// Raw scancodes pushed by the keyboard interrupt handler. They are only
+// decoded once someone reads them, so the handler never has to lock the
+// decoder. Both the blocking read_key path and ScancodeStream drain this
+static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+// Needs the heap, so this runs after allocator::init_heap. Scancodes that
+// arrive before that are dropped
+pub fn init() {
+    SCANCODE_QUEUE
+        .try_init_once(|| ArrayQueue::new(SCANCODE_QUEUE_SIZE))
+        .expect("keyboard::init should only be called once");
+}
+
+// Called from the keyboard interrupt handler with every byte read from port
+// 0x60, so it must not block or allocate. Tests can call it directly to
+// inject scancodes
+pub fn add_scancode(scancode: u8) {
+    if let Ok(queue) = SCANCODE_QUEUE.try_get() {
+        // If nobody is reading keys the queue fills up, newer keys are dropped
+        if queue.push(scancode).is_ok() {
+            WAKER.wake();
+        }
+    }
+}
+
+// Which modifier keys are held, and which locks are on. pc-keyboard keeps
+// its own copy for decoding but doesn't expose it, and has no plain Alt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub lshift: bool,
+    pub rshift: bool,
+    pub lctrl: bool,
+    pub rctrl: bool,
+    pub lalt: bool,
+    pub ralt: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+impl Modifiers {
+    // Num lock starts out on, like pc-keyboard assumes
+    const fn new() -> Modifiers {
+        Modifiers {
+            lshift: false,
+            rshift: false,
+            lctrl: false,
+            rctrl: false,
+            lalt: false,
+            ralt: false,
+            caps_lock: false,
+            num_lock: true,
+        }
+    }
+    pub fn shift(&self) -> bool {
+        self.lshift || self.rshift
+    }
+    pub fn ctrl(&self) -> bool {
+        self.lctrl || self.rctrl
+    }
+    pub fn alt(&self) -> bool {
+        self.lalt || self.ralt
+    }
+    fn update(&mut self, code: KeyCode, down: bool) {
+        match code {
+            KeyCode::ShiftLeft => self.lshift = down,
+            KeyCode::ShiftRight => self.rshift = down,
+            KeyCode::ControlLeft => self.lctrl = down,
+            KeyCode::ControlRight => self.rctrl = down,
+            KeyCode::AltLeft => self.lalt = down,
+            KeyCode::AltRight => self.ralt = down,
+            KeyCode::CapsLock if down => self.caps_lock = !self.caps_lock,
+            KeyCode::NumpadLock if down => self.num_lock = !self.num_lock,
+            _ => {}
+        }
+    }
+}
+
+// Updated as scancodes get decoded, which happens when keys are read rather
+// than in the interrupt handler, so it lags behind unread keys
+static MODIFIERS: Mutex<Modifiers> = Mutex::new(Modifiers::new());
+
+pub fn modifiers() -> Modifiers {
+    *MODIFIERS.lock()
+}
+
+// Alt plus one of these switches to that virtual console
+const CONSOLE_KEYS: [KeyCode; CONSOLE_COUNT] = [KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4];
+
+fn decode(scancode: u8) -> Option<DecodedKey> {
+    let mut keyboard = KEYBOARD.lock();
+    let key_event = match keyboard.add_byte(scancode) {
+        Ok(Some(key_event)) => key_event,
+        _ => return None,
+    };
+    let down = key_event.state == KeyState::Down;
+    let alt = {
+        let mut modifiers = MODIFIERS.lock();
+        modifiers.update(key_event.code, down);
+        modifiers.alt()
+    };
+    // Only recorded here, decoding can happen while WRITER is held (a
+    // dialog waiting for a key, say). The key is still handed on, which gives
+    // whoever reads it a turn to apply the switch once the lock is free
+    if down && alt {
+        if let Some(console) = CONSOLE_KEYS.iter().position(|&key| key == key_event.code) {
+            vga_buffer::request_console_switch(console);
+        }
+    }
+    keyboard.process_keyevent(key_event)
+}
+
+fn queue_is_empty() -> bool {
+    SCANCODE_QUEUE
+        .try_get()
+        .map_or(true, |queue| queue.is_empty())
+}
+
+pub fn try_read_key() -> Option<DecodedKey> {
+    let queue = SCANCODE_QUEUE.try_get().ok()?;
+    while let Some(scancode) = queue.pop() {
+        if let Some(key) = decode(scancode) {
+            return Some(key);
+        }
+    }
+    None
+}
+
+// Skips over keys that aren't characters, like the arrows. Never blocks, so
+// async tasks can poll it
+pub fn try_read_char() -> Option<char> {
+    loop {
+        if let DecodedKey::Unicode(character) = try_read_key()? {
+            return Some(character);
+        }
+    }
+}
+
+pub fn read_key() -> DecodedKey {
+    loop {
+        if let Some(key) = try_read_key() {
+            return key;
+        }
+        // Check the queue again with interrupts off, then enable them and
+        // halt in one step so a key arriving in between still wakes us up
+        interrupts::disable();
+        if queue_is_empty() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    pub fn new() -> Self {
+        assert!(
+            SCANCODE_QUEUE.is_initialized(),
+            "keyboard::init has not been called"
+        );
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let queue = SCANCODE_QUEUE.try_get().unwrap();
+
+        // Fast path, skips registering the waker
+        if let Some(scancode) = queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        // Register before checking again, otherwise a scancode pushed
+        // between the two pops would never wake this task
+        WAKER.register(cx.waker());
+        match queue.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+// The async counterpart of read_key
+pub async fn next_key(scancodes: &mut ScancodeStream) -> DecodedKey {
+    loop {
+        // The stream never ends, it waits for more scancodes instead
+        if let Some(scancode) = scancodes.next().await {
+            if let Some(key) = decode(scancode) {
+                return key;
+            }
+        }
+    }
+}
+
+pub async fn print_keypresses() {
+    let mut scancodes = ScancodeStream::new();
+    while let Some(scancode) = scancodes.next().await {
+        if let Some(key) = decode(scancode) {
+            match key {
+                DecodedKey::Unicode(character) => print!("{}", character),
+                DecodedKey::RawKey(key) => print!("{:?}", key),
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_set_layout() {
+    set_layout(KeyboardLayout::Azerty);
+    // The key labelled Q on a US keyboard is A on AZERTY
+    add_scancode(0x10);
+    add_scancode(0x90);
+    let key = try_read_key();
+    set_layout(KeyboardLayout::Us104Key);
+    assert_eq!(key, Some(DecodedKey::Unicode('a')));
+}
+
+// Relies on print! going through WRITER
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_alt_function_key() {
+    // Alt down, F2 down and up, Alt up
+    for scancode in [0x38, 0x3c, 0xbc, 0xb8] {
+        add_scancode(scancode);
+    }
+    assert_eq!(try_read_key(), Some(DecodedKey::RawKey(KeyCode::F2)));
+    // Decoding only asks for the switch, it happens on the next print
+    assert_eq!(vga_buffer::active_console(), 0);
+    print!("");
+    assert_eq!(vga_buffer::active_console(), 1);
+    vga_buffer::switch_console(0);
+    // Without Alt, F2 is just a key
+    add_scancode(0x3c);
+    add_scancode(0xbc);
+    assert_eq!(try_read_key(), Some(DecodedKey::RawKey(KeyCode::F2)));
+    print!("");
+    assert_eq!(vga_buffer::active_console(), 0);
+}
+
+#[test_case]
+fn test_try_read_char() {
+    while try_read_key().is_some() {}
+    assert_eq!(try_read_char(), None);
+    // An arrow key, then "a"
+    for scancode in [0xe0, 0x4b, 0xe0, 0xcb, 0x1e, 0x9e] {
+        add_scancode(scancode);
+    }
+    assert_eq!(try_read_char(), Some('a'));
+    assert_eq!(try_read_char(), None);
+}
+
+#[test_case]
+fn test_modifiers() {
+    // Left Ctrl down, then "l"
+    for scancode in [0x1d, 0x26, 0xa6] {
+        add_scancode(scancode);
+    }
+    assert_eq!(try_read_key(), Some(DecodedKey::Unicode('\u{c}')));
+    let held = modifiers();
+    assert!(held.lctrl && held.ctrl() && !held.rctrl);
+    assert!(!held.shift() && !held.alt());
+    // Ctrl up, then Caps Lock pressed and released
+    for scancode in [0x9d, 0x3a, 0xba] {
+        add_scancode(scancode);
+    }
+    assert_eq!(try_read_key(), None);
+    let released = modifiers();
+    assert!(!released.ctrl());
+    assert_eq!(released.caps_lock, !held.caps_lock);
+    add_scancode(0x3a);
+    add_scancode(0xba);
+    assert_eq!(try_read_key(), None);
+    assert_eq!(modifiers().caps_lock, held.caps_lock);
+}
+
+#[test_case]
+fn test_scancode_set_2() {
+    set_scancode_set(ScancodeSet::Set2);
+    // "a" pressed and released, then the extended Left Arrow press
+    for scancode in [0x1c, 0xf0, 0x1c, 0xe0, 0x6b] {
+        add_scancode(scancode);
+    }
+    let a = try_read_key();
+    let arrow = try_read_key();
+    set_scancode_set(ScancodeSet::Set1);
+    assert_eq!(a, Some(DecodedKey::Unicode('a')));
+    assert_eq!(arrow, Some(DecodedKey::RawKey(KeyCode::ArrowLeft)));
+    assert_eq!(scancode_set(), ScancodeSet::Set1);
+}
@@ -2,17 +2,41 @@
 #![cfg_attr(test, no_main)]
 #![feature(custom_test_frameworks)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
+#![feature(const_mut_refs)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+extern crate alloc;
+
 use core::panic::PanicInfo;
+pub mod allocator;
+pub mod console;
+pub mod cpu;
+pub mod debug;
+pub mod demos;
+pub mod fmt;
 pub mod gdt;
 pub mod interrupts;
+pub mod keyboard;
+pub mod log;
+pub mod memory;
+pub mod pit;
+pub mod power;
+pub mod ramfs;
+pub mod rng;
+pub mod rtc;
 pub mod serial;
+pub mod shell;
+pub mod speaker;
+pub mod sync;
+pub mod task;
+pub mod tsc;
 pub mod vga_buffer;
+pub mod watchdog;
 
 pub trait Testable {
-    fn run(&self) -> ();
+    fn run(&self);
 }
 
 impl<T> Testable for T
@@ -42,11 +66,25 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
 }
 
 #[cfg(test)]
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
+use bootloader::{entry_point, BootInfo};
+
+#[cfg(test)]
+entry_point!(test_kernel_main);
+
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
+    use memory::BootInfoFrameAllocator;
+    use x86_64::VirtAddr;
+
     init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    vga_buffer::init_scrollback();
+    keyboard::init();
     test_main();
-    loop {}
+    hlt_loop();
 }
 
 #[cfg(test)]
@@ -55,6 +93,10 @@ fn panic(info: &PanicInfo) -> ! {
     test_panic_handler(info)
 }
 
+// Exiting needs QEMU to be started with
+// `-device isa-debug-exit,iobase=0xf4,iosize=0x04` (see test-args in
+// Cargo.toml). QEMU exits with `(code << 1) | 1`, so Success shows up on the
+// host as 33, which is what test-success-exit-code expects
 #[repr(u32)]
 pub enum QemuExitCode {
     Success = 0x10,
@@ -71,9 +113,21 @@ pub fn init() {
     gdt::init();
     interrupts::init_idt();
     unsafe { interrupts::PICS.lock().initialize() };
+    serial::init();
     x86_64::instructions::interrupts::enable();
 }
 
+#[alloc_error_handler]
+fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
+    cprintln!(
+        vga_buffer::Color::Red,
+        "allocation error: size {} align {}",
+        layout.size(),
+        layout.align()
+    );
+    panic!("allocation error: {:?}", layout)
+}
+
 pub fn hlt_loop() -> ! {
     loop {
         x86_64::instructions::hlt();
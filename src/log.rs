@@ -0,0 +1,110 @@
+use crate::vga_buffer::Color;
+use crate::{cprint, println};
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+use x86_64::instructions::interrupts;
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => ($crate::log::_log($level, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        }
+    }
+    fn color(self) -> Color {
+        match self {
+            Level::Info => Color::Green,
+            Level::Warn => Color::Yellow,
+            Level::Error => Color::Red,
+        }
+    }
+}
+
+// A lock here could be held by whoever a logging interrupt handler
+// interrupted, so the level is kept as its discriminant instead
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+pub fn set_min_level(level: Level) {
+    MIN_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn min_level() -> Level {
+    match MIN_LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Info,
+        1 => Level::Warn,
+        _ => Level::Error,
+    }
+}
+
+pub fn enabled(level: Level) -> bool {
+    level >= min_level()
+}
+
+pub fn _log(level: Level, args: fmt::Arguments) {
+    // Checked before anything is formatted so dropped messages stay cheap
+    if !enabled(level) {
+        return;
+    }
+    // Keep the prefix and message together on screen
+    interrupts::without_interrupts(|| {
+        cprint!(level.color(), "[{}] ", level.name());
+        println!("{}", args);
+    });
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_min_level() {
+    use crate::vga_buffer::WRITER;
+    interrupts::without_interrupts(|| {
+        let old_level = min_level();
+        set_min_level(Level::Error);
+        let before = {
+            let writer = WRITER.lock();
+            (writer.row_position, writer.column_position)
+        };
+        crate::warn!("should not be printed");
+        let after = {
+            let writer = WRITER.lock();
+            (writer.row_position, writer.column_position)
+        };
+        assert_eq!(before, after);
+        assert!(!enabled(Level::Warn));
+        assert!(enabled(Level::Error));
+        println!();
+        crate::error!("shown");
+        let writer = WRITER.lock();
+        let row = writer.read_row_trimmed(writer.row_position - 1);
+        assert_eq!(&row[..], b"[ERROR] shown");
+        drop(writer);
+        set_min_level(old_level);
+    })
+}
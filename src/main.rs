@@ -4,28 +4,91 @@
 #![test_runner(rust_os::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
+use rust_os::memory::{self, BootInfoFrameAllocator};
 use rust_os::println;
+use rust_os::task::{executor::Executor, Task};
+use rust_os::{allocator, keyboard, shell, vga_buffer};
+#[cfg(not(test))]
+use rust_os::{cprintln, serial_println, vga_buffer::Color};
+use x86_64::VirtAddr;
+
+entry_point!(kernel_main);
 
-#[no_mangle]
-pub extern "C" fn _start() -> ! {
+fn kernel_main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    vga_buffer::init_scrollback();
     for i in 1..10 {
         println!("Line {}", i)
     }
-    rust_os::init();
-    // Print Hello World to screen
+    keyboard::init();
     #[cfg(test)]
     test_main();
-    rust_os::hlt_loop();
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(shell::run()));
+    executor.spawn(Task::new(shell::run_serial()));
+    executor.run();
 }
 
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
+    x86_64::instructions::interrupts::disable();
+    // The panic may have happened while WRITER or SERIAL1 was locked, and
+    // with a single CPU nobody is ever going to release it, so locking again
+    // would spin forever. Forcing the lock open is risky too, since whoever
+    // held it may have left the writer halfway through an update (mid scroll
+    // for example), but at worst that garbles the screen, which is still
+    // better than a silent hang
+    unsafe {
+        rust_os::vga_buffer::force_unlock();
+        rust_os::serial::SERIAL1.force_unlock();
+    }
+    // Make sure the message reaches the screen even mid batch draw or
+    // while a line is being read
+    {
+        let mut writer = rust_os::vga_buffer::WRITER.lock();
+        writer.set_auto_flush(true);
+        writer.input_mode = false;
+    }
+    let _ = write_fault_details(info);
+    cprintln!(Color::Red, "{}", info);
+    serial_println!("{}", info);
     rust_os::hlt_loop();
 }
 
+// The panic message runs arbitrary Display impls, which a kernel in a bad
+// enough state can trip over again. So before it, the numbers here are
+// written without the formatting machinery, and the location and the last
+// page fault address make it out even if printing the message doesn't. CR2
+// is only updated by page faults, so for any other panic it is left over
+// from an earlier one
+#[cfg(not(test))]
+fn write_fault_details(info: &PanicInfo) -> core::fmt::Result {
+    use core::fmt::Write;
+    use rust_os::fmt::{write_hex, write_u64};
+    use x86_64::registers::control::Cr2;
+
+    let mut serial = rust_os::serial::SERIAL1.lock();
+    if let Some(location) = info.location() {
+        serial.write_str("location: ")?;
+        serial.write_str(location.file())?;
+        serial.write_char(':')?;
+        write_u64(&mut *serial, u64::from(location.line()))?;
+        serial.write_char(':')?;
+        write_u64(&mut *serial, u64::from(location.column()))?;
+        serial.write_char('\n')?;
+    }
+    serial.write_str("last page fault address (CR2): ")?;
+    write_hex(&mut *serial, Cr2::read().as_u64())?;
+    serial.write_char('\n')
+}
+
 #[cfg(test)]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
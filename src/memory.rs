@@ -0,0 +1,118 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Returns a mapper for the currently active page tables
+///
+/// # Safety
+/// The bootloader maps all of physical memory at `physical_memory_offset`
+/// (needs its `map_physical_memory` feature) and passes the offset to the
+/// kernel in BootInfo. The whole of physical memory has to actually be mapped
+/// there, and this must only be called once to avoid aliasing `&mut` page
+/// table references
+pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let level_4_table = active_level_4_table(physical_memory_offset);
+    OffsetPageTable::new(level_4_table, physical_memory_offset)
+}
+
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_table_frame, _) = Cr3::read();
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+    &mut *page_table_ptr
+}
+
+// Maps the given page to the VGA text buffer frame, to check translation works
+pub fn create_example_mapping(
+    page: Page,
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) {
+    use x86_64::structures::paging::PageTableFlags as Flags;
+
+    let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+    let flags = Flags::PRESENT | Flags::WRITABLE;
+
+    // Not safe in general, the frame is already in use by the VGA buffer so
+    // this creates an aliasing mapping
+    let map_to_result = unsafe { mapper.map_to(page, frame, flags, frame_allocator) };
+    map_to_result.expect("map_to failed").flush();
+}
+
+// Stacks are handed out upwards from here. Each one sits right above an
+// unmapped guard page, so running off the bottom page faults at a known
+// address instead of running into whatever is mapped below
+const STACK_REGION_START: u64 = 0x_5555_5555_0000;
+static NEXT_STACK: AtomicU64 = AtomicU64::new(STACK_REGION_START);
+
+// Maps a new stack of `pages` pages and returns its top, which is what goes
+// in rsp. The guard page is the page just below the lowest mapped one
+pub fn map_stack_with_guard(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    pages: usize,
+) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let size = pages as u64 * Page::<Size4KiB>::SIZE;
+    let guard = NEXT_STACK.fetch_add(size + Page::<Size4KiB>::SIZE, Ordering::Relaxed);
+    let stack_start = Page::containing_address(VirtAddr::new(guard + Page::<Size4KiB>::SIZE));
+    for page in Page::range(stack_start, stack_start + pages as u64) {
+        let frame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    }
+    Ok(stack_start.start_address() + size)
+}
+
+// A frame allocator that always fails, enough when no new page tables are needed
+pub struct EmptyFrameAllocator;
+
+unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        None
+    }
+}
+
+// Hands out the usable frames from the bootloader's memory map. Frames are
+// never given back, `next` just counts how many have been handed out so far
+pub struct BootInfoFrameAllocator {
+    memory_map: &'static MemoryMap,
+    next: usize,
+}
+
+impl BootInfoFrameAllocator {
+    /// # Safety
+    /// The memory map has to be valid, every frame marked `Usable` in it must
+    /// really be unused
+    pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
+        BootInfoFrameAllocator {
+            memory_map,
+            next: 0,
+        }
+    }
+
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+        self.memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable)
+            .map(|r| r.range.start_addr()..r.range.end_addr())
+            .flat_map(|r| r.step_by(4096))
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let frame = self.usable_frames().nth(self.next);
+        self.next += 1;
+        frame
+    }
+}
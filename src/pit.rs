@@ -0,0 +1,126 @@
+use crate::interrupts::ticks;
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+pub const PIT_BASE_FREQUENCY: u32 = 1_193_182;
+
+const CHANNEL_0: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+
+// A reload value of 0 means 65536, which is what the BIOS leaves channel 0
+// at, giving the familiar ~18.2 Hz timer
+static DIVISOR: AtomicU32 = AtomicU32::new(65536);
+
+// Programs channel 0, which drives the timer interrupt, to fire at roughly
+// `hz`. The rate is rounded to what the divisor allows (about 19 Hz up to
+// the base frequency)
+pub fn set_frequency(hz: u32) {
+    let divisor = (PIT_BASE_FREQUENCY / hz.max(1)).clamp(1, 65536);
+    let mut command_port: Port<u8> = Port::new(COMMAND);
+    let mut data_port: Port<u8> = Port::new(CHANNEL_0);
+    interrupts::without_interrupts(|| unsafe {
+        // Channel 0, low byte then high byte, mode 3 (square wave)
+        command_port.write(0x36);
+        data_port.write((divisor & 0xFF) as u8);
+        data_port.write(((divisor >> 8) & 0xFF) as u8);
+    });
+    DIVISOR.store(divisor, Ordering::Relaxed);
+}
+
+pub fn divisor() -> u32 {
+    DIVISOR.load(Ordering::Relaxed)
+}
+
+pub fn frequency() -> u32 {
+    PIT_BASE_FREQUENCY / divisor()
+}
+
+// Rounds up, so sleeping never ends early
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    let denominator = u64::from(divisor()) * 1000;
+    (ms * u64::from(PIT_BASE_FREQUENCY) + denominator - 1) / denominator
+}
+
+// Needs interrupts to be enabled, otherwise the tick count never moves
+pub fn sleep_ms(ms: u64) {
+    let target = ticks() + ms_to_ticks(ms);
+    while ticks() < target {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uptime {
+    pub hours: u64,
+    pub minutes: u64,
+    pub seconds: u64,
+}
+
+impl Uptime {
+    // Uses the current divisor for every tick, so the result is off if the
+    // frequency was changed since boot. If it was never set the divisor is
+    // still the BIOS default
+    pub fn from_ticks(ticks: u64) -> Uptime {
+        let total = u128::from(ticks) * u128::from(divisor()) / u128::from(PIT_BASE_FREQUENCY);
+        let total = total as u64;
+        Uptime {
+            hours: total / 3600,
+            minutes: total / 60 % 60,
+            seconds: total % 60,
+        }
+    }
+}
+
+impl fmt::Display for Uptime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "up {:02}:{:02}:{:02}",
+            self.hours, self.minutes, self.seconds
+        )
+    }
+}
+
+// (hours, minutes, seconds) since the timer started
+pub fn uptime() -> (u64, u64, u64) {
+    let uptime = Uptime::from_ticks(ticks());
+    (uptime.hours, uptime.minutes, uptime.seconds)
+}
+
+#[test_case]
+fn test_ms_to_ticks() {
+    let old_divisor = divisor();
+    DIVISOR.store(65536, Ordering::Relaxed);
+    assert_eq!(ms_to_ticks(0), 0);
+    // 18.2 ticks in a second, rounded up
+    assert_eq!(ms_to_ticks(1000), 19);
+    DIVISOR.store(old_divisor, Ordering::Relaxed);
+}
+
+#[test_case]
+fn test_sleep_ms() {
+    let old_frequency = frequency();
+    set_frequency(1000);
+    let start = ticks();
+    sleep_ms(20);
+    let elapsed = ticks() - start;
+    set_frequency(old_frequency);
+    // Waking up can be a tick late, and the count can be read just before
+    // the next one, but a sleep that ignores its deadline shows up here
+    assert!((20..=22).contains(&elapsed), "slept {} ticks", elapsed);
+}
+
+#[test_case]
+fn test_uptime_format() {
+    use core::fmt::Write;
+    let old_divisor = divisor();
+    DIVISOR.store(65536, Ordering::Relaxed);
+    // Just over 1h 2m 3s at ~18.2 Hz
+    let uptime = Uptime::from_ticks(67784);
+    DIVISOR.store(old_divisor, Ordering::Relaxed);
+    let mut text = heapless::String::<16>::new();
+    write!(text, "{}", uptime).unwrap();
+    assert_eq!(text.as_str(), "up 01:02:03");
+}
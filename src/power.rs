@@ -0,0 +1,60 @@
+use crate::hlt_loop;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::instructions::tables::lidt;
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+// Ports that power the machine off when the right value is written, and
+// that value. These only exist on emulators. Real hardware wants ACPI, which
+// means finding the FADT and evaluating \_S5 in the DSDT to get the sleep
+// type, and there is no AML interpreter here for that
+pub const EMULATOR_POWER_OFF: [(u16, u16); 2] = [
+    // QEMU's ACPI PM control register
+    (0x604, 0x2000),
+    // Bochs, and QEMU before 2.0
+    (0xB004, 0x2000),
+];
+
+pub fn shutdown() -> ! {
+    power_off(&EMULATOR_POWER_OFF)
+}
+
+// Writes to each port in turn, the first one that works never returns. If
+// the machine is still running after all of them, it halts instead
+pub fn power_off(ports: &[(u16, u16)]) -> ! {
+    interrupts::disable();
+    for &(port, value) in ports {
+        unsafe { Port::new(port).write(value) };
+    }
+    hlt_loop();
+}
+
+// PC specific. The 8042 keyboard controller drives the CPU's reset line, and
+// command 0xFE pulses it. Machines without one (or where it is emulated
+// badly) get a triple fault instead: with an empty IDT the breakpoint can't
+// be delivered, neither can the double fault that follows, so the CPU resets
+pub fn reboot() -> ! {
+    interrupts::disable();
+    let mut controller: Port<u8> = Port::new(0x64);
+    unsafe {
+        // The controller ignores commands until its input buffer is empty
+        for _ in 0..0x10000 {
+            if controller.read() & 0x02 == 0 {
+                break;
+            }
+        }
+        controller.write(0xFE);
+    }
+    triple_fault();
+}
+
+fn triple_fault() -> ! {
+    let empty = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::new(0),
+    };
+    unsafe { lidt(&empty) };
+    interrupts::int3();
+    hlt_loop();
+}
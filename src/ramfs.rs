@@ -0,0 +1,49 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+// A flat namespace, a path is just a key and there are no directories.
+// Everything lives on the heap and is gone after a reboot
+lazy_static! {
+    static ref FILES: Mutex<BTreeMap<String, Vec<u8>>> = Mutex::new(BTreeMap::new());
+}
+
+fn with_files<R>(f: impl FnOnce(&mut BTreeMap<String, Vec<u8>>) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut FILES.lock()))
+}
+
+// Creates the file, or replaces its contents if it already exists
+pub fn write_file(path: &str, data: &[u8]) {
+    with_files(|files| files.insert(String::from(path), Vec::from(data)));
+}
+
+pub fn read_file(path: &str) -> Option<Vec<u8>> {
+    with_files(|files| files.get(path).cloned())
+}
+
+// Sorted by path
+pub fn list() -> Vec<String> {
+    with_files(|files| files.keys().cloned().collect())
+}
+
+// Returns false if there was no such file
+pub fn delete(path: &str) -> bool {
+    with_files(|files| files.remove(path).is_some())
+}
+
+#[test_case]
+fn test_ramfs() {
+    write_file("/test/a", b"first");
+    write_file("/test/b", b"second");
+    let paths = list();
+    assert!(paths.iter().any(|path| path == "/test/a"));
+    assert!(paths.iter().any(|path| path == "/test/b"));
+    assert_eq!(read_file("/test/b").as_deref(), Some(&b"second"[..]));
+    assert!(delete("/test/a"));
+    assert!(!delete("/test/a"));
+    assert_eq!(read_file("/test/a"), None);
+    delete("/test/b");
+}
@@ -0,0 +1,96 @@
+use crate::tsc;
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::random::RdRand;
+
+// xorshift64*. Small and quick, good enough for demos and for shuffling
+// test inputs, but the output is easy to predict so never use it for keys
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub const fn new(seed: u64) -> Rng {
+        // An all zero state only ever produces zeros
+        let state = if seed == 0 {
+            0x9E37_79B9_7F4A_7C15
+        } else {
+            seed
+        };
+        Rng { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // Uniform in lo..hi, which must not be empty. Plain `% span` would favor
+    // the low values, so draws from the partial bucket at the top of the
+    // u64 range are thrown away and redrawn
+    pub fn next_range(&mut self, lo: u64, hi: u64) -> u64 {
+        assert!(lo < hi, "empty range {}..{}", lo, hi);
+        let span = hi - lo;
+        let limit = u64::MAX - u64::MAX % span;
+        loop {
+            let x = self.next_u64();
+            if x < limit {
+                return lo + x % span;
+            }
+        }
+    }
+}
+
+// RDRAND when the CPU has it, otherwise the TSC, which at least differs
+// from boot to boot
+pub fn seed() -> u64 {
+    RdRand::new()
+        .and_then(|rdrand| rdrand.get_u64())
+        .unwrap_or_else(tsc::rdtsc)
+}
+
+lazy_static! {
+    static ref RNG: Mutex<Rng> = Mutex::new(Rng::new(seed()));
+}
+
+pub fn next_u64() -> u64 {
+    interrupts::without_interrupts(|| RNG.lock().next_u64())
+}
+
+pub fn next_range(lo: u64, hi: u64) -> u64 {
+    interrupts::without_interrupts(|| RNG.lock().next_range(lo, hi))
+}
+
+#[test_case]
+fn test_fixed_seed() {
+    let mut rng = Rng::new(42);
+    assert_eq!(rng.next_u64(), 0x56ce_4ab7_719b_a3a0);
+    assert_eq!(rng.next_u64(), 0xc841_eb53_ebbb_2dda);
+    assert_eq!(rng.next_u64(), 0xca46_6be0_c998_0276);
+    let mut a = Rng::new(7);
+    let mut b = Rng::new(7);
+    for _ in 0..100 {
+        assert_eq!(a.next_u64(), b.next_u64());
+    }
+}
+
+#[test_case]
+fn test_next_range() {
+    let mut rng = Rng::new(1);
+    let mut seen = [false; 6];
+    for _ in 0..1000 {
+        let x = rng.next_range(10, 16);
+        assert!((10..16).contains(&x));
+        seen[(x - 10) as usize] = true;
+    }
+    assert!(seen.iter().all(|&seen| seen));
+    assert_eq!(rng.next_range(5, 6), 5);
+    let x = next_range(u64::MAX - 3, u64::MAX);
+    assert!((u64::MAX - 3..u64::MAX).contains(&x));
+}
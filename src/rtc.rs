@@ -0,0 +1,119 @@
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn read_register(register: u8) -> u8 {
+    let mut address_port: Port<u8> = Port::new(CMOS_ADDRESS);
+    let mut data_port: Port<u8> = Port::new(CMOS_DATA);
+    unsafe {
+        address_port.write(register);
+        data_port.read()
+    }
+}
+
+fn update_in_progress() -> bool {
+    read_register(REG_STATUS_A) & 0x80 != 0
+}
+
+fn read_raw() -> DateTime {
+    while update_in_progress() {}
+    DateTime {
+        year: u16::from(read_register(REG_YEAR)),
+        month: read_register(REG_MONTH),
+        day: read_register(REG_DAY),
+        hour: read_register(REG_HOURS),
+        minute: read_register(REG_MINUTES),
+        second: read_register(REG_SECONDS),
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+// The CMOS only keeps a two digit year, assume we're in the 2000s
+pub fn read() -> DateTime {
+    // Selecting a register and reading it takes two port accesses, an
+    // interrupt handler touching the CMOS in between would mess that up
+    interrupts::without_interrupts(|| {
+        // The clock can still tick over while the fields are being read, so
+        // keep reading until two reads in a row agree
+        let mut time = read_raw();
+        loop {
+            let next = read_raw();
+            if next == time {
+                break;
+            }
+            time = next;
+        }
+
+        let status_b = read_register(REG_STATUS_B);
+        let binary = status_b & 0x04 != 0;
+        let twenty_four_hour = status_b & 0x02 != 0;
+
+        // In 12 hour mode the top bit of the hour marks PM
+        let pm = !twenty_four_hour && time.hour & 0x80 != 0;
+        time.hour &= 0x7F;
+
+        if !binary {
+            time.second = bcd_to_binary(time.second);
+            time.minute = bcd_to_binary(time.minute);
+            time.hour = bcd_to_binary(time.hour);
+            time.day = bcd_to_binary(time.day);
+            time.month = bcd_to_binary(time.month);
+            time.year = u16::from(bcd_to_binary(time.year as u8));
+        }
+
+        if !twenty_four_hour {
+            // 12 AM is midnight and 12 PM is noon
+            time.hour %= 12;
+            if pm {
+                time.hour += 12;
+            }
+        }
+
+        time.year += 2000;
+        time
+    })
+}
+
+#[test_case]
+fn test_bcd_to_binary() {
+    assert_eq!(bcd_to_binary(0x00), 0);
+    assert_eq!(bcd_to_binary(0x59), 59);
+    assert_eq!(bcd_to_binary(0x12), 12);
+}
+
+#[test_case]
+fn test_read_ranges() {
+    for _ in 0..2 {
+        let time = read();
+        assert!((2000..2100).contains(&time.year));
+        assert!((1..=12).contains(&time.month));
+        assert!((1..=31).contains(&time.day));
+        assert!(time.hour < 24);
+        assert!(time.minute < 60);
+        assert!(time.second < 60);
+    }
+}
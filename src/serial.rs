@@ -1,8 +1,18 @@
+use crate::console::Console;
+use crate::interrupts::{InterruptIndex, PICS, PIC_1_OFFSET};
+use crate::vga_buffer::Color;
+use core::fmt::{self, Write};
+use core::task::Poll;
+use futures_util::future;
+use futures_util::task::AtomicWaker;
+use heapless::mpmc::MpMcQueue;
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
 use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
 
+// COM1. Run QEMU with `-serial stdio` to see this output on the host terminal
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
         let mut serial_port = unsafe { SerialPort::new(0x3F8) };
@@ -11,9 +21,118 @@ lazy_static! {
     };
 }
 
-pub fn _print(args: ::core::fmt::Arguments) {
-    use core::fmt::Write;
+// The UART has a scratch register that nothing else uses. With no UART at
+// the port the write goes nowhere and the read gives back 0xFF
+pub fn is_present() -> bool {
+    static PRESENT: spin::Once<bool> = spin::Once::new();
+    *PRESENT.call_once(|| {
+        let mut scratch: Port<u8> = Port::new(0x3F8 + 7);
+        unsafe {
+            scratch.write(0x5A);
+            scratch.read() == 0x5A
+        }
+    })
+}
+
+// Bytes received on COM1, filled by the IRQ4 handler. Lock free so the
+// handler never waits on a reader
+static RX_QUEUE: MpMcQueue<u8, 128> = MpMcQueue::new();
+static RX_WAKER: AtomicWaker = AtomicWaker::new();
+
+// SerialPort::init already turns on the UART's received data interrupt, but
+// SERIAL1 is only created on first use and IRQ4 may be masked by the BIOS
+pub fn init() {
+    lazy_static::initialize(&SERIAL1);
+    let line = InterruptIndex::Serial1.as_u8() - PIC_1_OFFSET;
+    interrupts::without_interrupts(|| unsafe {
+        let mut pics = PICS.lock();
+        let [master, slave] = pics.read_masks();
+        pics.write_masks(master & !(1 << line), slave);
+    });
+}
+
+// Called from the COM1 interrupt handler for every byte read from the data
+// register, so it must not block or allocate. Tests can call it directly to
+// inject input. When the queue is full newer bytes are dropped
+pub fn add_byte(byte: u8) {
+    if RX_QUEUE.enqueue(byte).is_ok() {
+        RX_WAKER.wake();
+    }
+}
+
+pub fn read_byte() -> Option<u8> {
+    RX_QUEUE.dequeue()
+}
+
+// Waits for the next received byte without spinning, for use from tasks
+pub async fn next_byte() -> u8 {
+    future::poll_fn(|cx| {
+        // Same dance as ScancodeStream, check again after registering so a
+        // byte that arrives in between isn't missed
+        if let Some(byte) = read_byte() {
+            return Poll::Ready(byte);
+        }
+        RX_WAKER.register(cx.waker());
+        match read_byte() {
+            Some(byte) => {
+                RX_WAKER.take();
+                Poll::Ready(byte)
+            }
+            None => Poll::Pending,
+        }
+    })
+    .await
+}
+
+// Serial terminals speak ANSI, whose eight colors are ordered differently
+// from the VGA ones. The VGA bright bit picks the bright ANSI variant
+fn ansi_color(color: Color) -> u8 {
+    const FROM_VGA: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+    let color = color as u8;
+    let bright = if color & 8 != 0 { 60 } else { 0 };
+    FROM_VGA[usize::from(color & 7)] + bright
+}
+
+impl Console for SerialPort {
+    fn clear(&mut self) {
+        let _ = self.write_str("\x1b[2J\x1b[H");
+    }
+    fn set_color(&mut self, foreground: Color, background: Color) {
+        let _ = write!(
+            self,
+            "\x1b[{};{}m",
+            30 + ansi_color(foreground),
+            40 + ansi_color(background)
+        );
+    }
+    fn write_colored(&mut self, foreground: Color, args: fmt::Arguments) -> fmt::Result {
+        // 39 puts back the terminal's default foreground
+        write!(self, "\x1b[{}m", 30 + ansi_color(foreground))?;
+        self.write_fmt(args)?;
+        self.write_str("\x1b[39m")
+    }
+}
+
+// Tests can look at what would have been sent, see capture
+#[cfg(test)]
+static CAPTURE: Mutex<Option<alloc::string::String>> = Mutex::new(None);
+
+// Runs f and returns everything it printed to serial. That output is held
+// back from the UART, so it doesn't land in the middle of the test log
+#[cfg(test)]
+pub fn capture(f: impl FnOnce()) -> alloc::string::String {
+    interrupts::without_interrupts(|| *CAPTURE.lock() = Some(alloc::string::String::new()));
+    f();
+    interrupts::without_interrupts(|| CAPTURE.lock().take().unwrap())
+}
+
+pub fn _print(args: fmt::Arguments) {
     interrupts::without_interrupts(|| {
+        #[cfg(test)]
+        if let Some(captured) = CAPTURE.lock().as_mut() {
+            captured.write_fmt(args).unwrap();
+            return;
+        }
         SERIAL1
             .lock()
             .write_fmt(args)
@@ -35,3 +154,24 @@ macro_rules! serial_println {
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(
         concat!($fmt, "\n"), $($arg)*));
 }
+
+#[test_case]
+fn test_ansi_color() {
+    assert_eq!(ansi_color(Color::Black), 0);
+    assert_eq!(ansi_color(Color::Red), 1);
+    assert_eq!(ansi_color(Color::Brown), 3);
+    assert_eq!(ansi_color(Color::LightBlue), 64);
+    assert_eq!(ansi_color(Color::White), 67);
+}
+
+#[test_case]
+fn test_read_byte() {
+    while read_byte().is_some() {}
+    for &byte in b"hi\n" {
+        add_byte(byte);
+    }
+    assert_eq!(read_byte(), Some(b'h'));
+    assert_eq!(read_byte(), Some(b'i'));
+    assert_eq!(read_byte(), Some(b'\n'));
+    assert_eq!(read_byte(), None);
+}
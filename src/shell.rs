@@ -0,0 +1,525 @@
+use crate::allocator;
+#[cfg(test)]
+use crate::console::Recorder;
+use crate::console::{self, Console};
+use crate::cpu;
+use crate::fmt::human_bytes;
+#[cfg(test)]
+use crate::keyboard;
+use crate::power;
+use crate::print;
+#[cfg(all(test, feature = "vga_text"))]
+use crate::println;
+use crate::ramfs;
+use crate::serial::{self, SERIAL1};
+#[cfg(all(test, feature = "vga_text"))]
+use crate::vga_buffer::WRITER;
+use crate::vga_buffer::{self, Color, History};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use x86_64::instructions::interrupts;
+
+const COMMANDS: &[(&str, &str)] = &[
+    ("cat", "cat <path>, print a file"),
+    ("clear", "clear the screen"),
+    ("color", "color <fg> <bg>, set the text color"),
+    ("cpuinfo", "show the processor and its features"),
+    ("echo", "echo <text>, print text"),
+    ("help", "list commands"),
+    ("lockstats", "show how contended the screen lock is"),
+    ("ls", "list files"),
+    ("meminfo", "show heap usage"),
+    ("reboot", "restart the machine"),
+    ("shutdown", "power off, only works in QEMU and Bochs"),
+    ("write", "write <path> <text>, save text to a file"),
+];
+
+pub async fn run() {
+    let mut history = History::new();
+    let names = command_names();
+    loop {
+        print!("> ");
+        let line = vga_buffer::read_line_with(&history, &names).await;
+        history.push(&line);
+        execute(&line);
+    }
+}
+
+// The same command loop on COM1, so the kernel can be driven headless with
+// `-serial stdio`. Line editing is left to the terminal, apart from Backspace
+pub async fn run_serial() {
+    let mut line = LineBuffer::default();
+    with_serial(|serial| serial.write_str("> "));
+    loop {
+        let byte = serial::next_byte().await;
+        with_serial(|serial| serve(serial, &mut line, byte));
+    }
+}
+
+fn with_serial(f: impl FnOnce(&mut dyn Console) -> fmt::Result) {
+    interrupts::without_interrupts(|| f(&mut *SERIAL1.lock())).expect("Printing to serial failed");
+}
+
+// Hands one received byte to the line, and runs the line once it's complete
+fn serve(console: &mut dyn Console, line: &mut LineBuffer, byte: u8) -> fmt::Result {
+    if let Some(line) = line.feed(console, byte)? {
+        execute_on(console, &line)?;
+        console.write_str("> ")?;
+    }
+    Ok(())
+}
+
+// Collects a line from a terminal that sends raw bytes, echoing them back.
+// Enter arrives as \r, \n or both, so a \n straight after a \r is skipped
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    line: String,
+    after_cr: bool,
+}
+
+impl LineBuffer {
+    pub fn feed(
+        &mut self,
+        console: &mut dyn Console,
+        byte: u8,
+    ) -> Result<Option<String>, fmt::Error> {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => {}
+            b'\r' | b'\n' => {
+                console.write_char('\n')?;
+                return Ok(Some(core::mem::take(&mut self.line)));
+            }
+            // Backspace, or DEL which is what most terminals send for it. The
+            // UART driver already turns \x08 into "\x08 \x08" to erase
+            0x08 | 0x7f => {
+                if self.line.pop().is_some() {
+                    console.write_char('\x08')?;
+                }
+            }
+            0x20..=0x7e => {
+                self.line.push(char::from(byte));
+                console.write_char(char::from(byte))?;
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+}
+
+// What Tab completes against
+fn command_names() -> heapless::Vec<&'static str, { COMMANDS.len() }> {
+    COMMANDS.iter().map(|(name, _)| *name).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    UnterminatedQuote,
+}
+
+// Splits a line into arguments on whitespace. Double quotes group words into
+// one argument, and a backslash takes the next character literally, inside
+// quotes or out
+pub fn tokenize(line: &str) -> Result<Vec<String>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    // Separate from token being empty, so "" is still an argument
+    let mut in_token = false;
+    let mut quoted = false;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                token.push(chars.next().unwrap_or('\\'));
+                in_token = true;
+            }
+            '"' => {
+                quoted = !quoted;
+                in_token = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_token {
+                    tokens.push(core::mem::take(&mut token));
+                    in_token = false;
+                }
+            }
+            c => {
+                token.push(c);
+                in_token = true;
+            }
+        }
+    }
+    if quoted {
+        return Err(ParseError::UnterminatedQuote);
+    }
+    if in_token {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// The argument following `--name`, if there is one
+pub fn flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let position = args
+        .iter()
+        .position(|arg| arg.strip_prefix("--") == Some(name))?;
+    args.get(position + 1).map(|value| value.as_str())
+}
+
+// Runs a line typed at the keyboard. The output goes where print! goes: the
+// console picked with console::set_active, otherwise the screen, or COM1
+// when built without VGA text output. Only called from task code, so unlike
+// print! it can wait for the lock
+pub fn execute(line: &str) {
+    interrupts::without_interrupts(|| {
+        if let Some(active) = console::active() {
+            return execute_on(&mut *active.lock(), line);
+        }
+        #[cfg(feature = "vga_text")]
+        let mut console = vga_buffer::WRITER.lock();
+        #[cfg(not(feature = "vga_text"))]
+        let mut console = SERIAL1.lock();
+        execute_on(&mut *console, line)
+    })
+    .expect("shell output failed");
+}
+
+pub fn execute_on(out: &mut dyn Console, line: &str) -> fmt::Result {
+    let tokens = match tokenize(line) {
+        Ok(tokens) => tokens,
+        Err(ParseError::UnterminatedQuote) => {
+            return out.write_colored(Color::Red, format_args!("unterminated quote\n"));
+        }
+    };
+    let (command, args) = match tokens.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => return Ok(()),
+    };
+    match command {
+        "cat" => cat(out, args),
+        "clear" => {
+            out.clear();
+            Ok(())
+        }
+        "color" => color(out, args),
+        "cpuinfo" => cpuinfo(out),
+        "echo" => writeln!(out, "{}", args.join(" ")),
+        "help" => help(out),
+        "lockstats" => lockstats(out),
+        "ls" => ls(out),
+        "meminfo" => meminfo(out),
+        "reboot" => reboot(out),
+        "shutdown" => power::shutdown(),
+        "write" => write(out, args),
+        _ => out.write_colored(Color::Red, format_args!("unknown command: {}\n", command)),
+    }
+}
+
+fn color(out: &mut dyn Console, args: &[String]) -> fmt::Result {
+    let foreground = args.get(0).and_then(|name| parse_color(name));
+    let background = args.get(1).and_then(|name| parse_color(name));
+    match (foreground, background) {
+        (Some(foreground), Some(background)) => {
+            out.set_color(foreground, background);
+            Ok(())
+        }
+        _ => out.write_colored(Color::Red, format_args!("usage: color <fg> <bg>\n")),
+    }
+}
+
+fn cpuinfo(out: &mut dyn Console) -> fmt::Result {
+    let info = cpu::info();
+    writeln!(out, "vendor:   {}", info.vendor)?;
+    writeln!(
+        out,
+        "brand:    {}",
+        info.brand.as_deref().unwrap_or("unknown")
+    )?;
+    write!(out, "features:")?;
+    for feature in &info.features {
+        write!(out, " {}", feature)?;
+    }
+    writeln!(out)
+}
+
+fn cat(out: &mut dyn Console, args: &[String]) -> fmt::Result {
+    let path = match args {
+        [path] => path,
+        _ => return out.write_colored(Color::Red, format_args!("usage: cat <path>\n")),
+    };
+    match ramfs::read_file(path) {
+        Some(data) => writeln!(out, "{}", String::from_utf8_lossy(&data)),
+        None => out.write_colored(Color::Red, format_args!("cat: no such file: {}\n", path)),
+    }
+}
+
+fn ls(out: &mut dyn Console) -> fmt::Result {
+    for path in ramfs::list() {
+        writeln!(out, "{}", path)?;
+    }
+    Ok(())
+}
+
+fn lockstats(out: &mut dyn Console) -> fmt::Result {
+    let stats = &vga_buffer::WRITER_STATS;
+    writeln!(
+        out,
+        "WRITER: {} acquisitions, {} spins",
+        stats.acquisitions(),
+        stats.spins()
+    )
+}
+
+// Rebooting in the middle of a test run would restart the whole kernel and
+// run every test again, forever
+#[cfg(not(test))]
+fn reboot(_out: &mut dyn Console) -> fmt::Result {
+    power::reboot()
+}
+
+#[cfg(test)]
+fn reboot(out: &mut dyn Console) -> fmt::Result {
+    writeln!(out, "reboot: not rebooting under test")
+}
+
+fn meminfo(out: &mut dyn Console) -> fmt::Result {
+    let stats = allocator::stats();
+    let bytes = |n: usize| human_bytes(n as u64);
+    writeln!(
+        out,
+        "heap: {} used, {} free, {} total, {} allocations",
+        bytes(stats.used),
+        bytes(stats.free),
+        bytes(stats.total),
+        stats.allocations
+    )
+}
+
+fn write(out: &mut dyn Console, args: &[String]) -> fmt::Result {
+    match args.split_first() {
+        Some((path, text)) if !text.is_empty() => {
+            ramfs::write_file(path, text.join(" ").as_bytes());
+            Ok(())
+        }
+        _ => out.write_colored(Color::Red, format_args!("usage: write <path> <text>\n")),
+    }
+}
+
+fn help(out: &mut dyn Console) -> fmt::Result {
+    for (name, description) in COMMANDS {
+        writeln!(out, "{:8}{}", name, description)?;
+    }
+    Ok(())
+}
+
+fn parse_color(name: &str) -> Option<Color> {
+    let color = match name {
+        "black" => Color::Black,
+        "blue" => Color::Blue,
+        "green" => Color::Green,
+        "cyan" => Color::Cyan,
+        "red" => Color::Red,
+        "magenta" => Color::Magenta,
+        "brown" => Color::Brown,
+        "lightgray" => Color::LightGray,
+        "darkgray" => Color::DarkGray,
+        "lightblue" => Color::LightBlue,
+        "lightgreen" => Color::LightGreen,
+        "lightcyan" => Color::LightCyan,
+        "lightred" => Color::LightRed,
+        "pink" => Color::Pink,
+        "yellow" => Color::Yellow,
+        "white" => Color::White,
+        _ => return None,
+    };
+    Some(color)
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_echo() {
+    println!();
+    execute("echo \"hello  world\"");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let row = writer.row_position - 1;
+        for (i, c) in "hello  world".chars().enumerate() {
+            let screen_char = writer.buffer.chars[row][i].read();
+            assert_eq!(char::from(screen_char.ascii_character), c);
+        }
+    })
+}
+
+#[test_case]
+fn test_tokenize() {
+    let tokens = tokenize("echo \"hello world\" again").unwrap();
+    assert_eq!(tokens, ["echo", "hello world", "again"]);
+    let tokens = tokenize(r#"say "a \"quoted\" word" \"bare\""#).unwrap();
+    assert_eq!(tokens, ["say", r#"a "quoted" word"#, r#""bare""#]);
+    // Surrounding whitespace never makes an argument, an empty quote does
+    assert_eq!(tokenize("  ls  \t ").unwrap(), ["ls"]);
+    assert_eq!(tokenize("echo \"\" ").unwrap(), ["echo", ""]);
+    assert!(tokenize("").unwrap().is_empty());
+    assert_eq!(tokenize("echo \"open"), Err(ParseError::UnterminatedQuote));
+}
+
+#[test_case]
+fn test_flag() {
+    let tokens = tokenize("color --fg yellow --bg \"light blue\" --last").unwrap();
+    assert_eq!(flag(&tokens, "fg"), Some("yellow"));
+    assert_eq!(flag(&tokens, "bg"), Some("light blue"));
+    assert_eq!(flag(&tokens, "last"), None);
+    assert_eq!(flag(&tokens, "yellow"), None);
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_clear() {
+    print!("something");
+    execute("clear");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        assert_eq!((writer.row_position, writer.column_position), (0, 0));
+        for col in 0..crate::vga_buffer::BUFFER_WIDTH {
+            assert_eq!(writer.buffer.chars[0][col].read().ascii_character, b' ');
+        }
+    })
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_color() {
+    execute("color yellow blue");
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let color = writer.color();
+        writer.set_color(Color::Yellow, Color::Blue);
+        assert_eq!(color, writer.color());
+        writer.set_color(Color::White, Color::Black);
+    })
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_write_cat() {
+    execute("write /shell/note some text");
+    assert_eq!(
+        ramfs::read_file("/shell/note").as_deref(),
+        Some(&b"some text"[..])
+    );
+    println!();
+    execute("cat /shell/note");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let row = writer.read_row_trimmed(writer.row_position - 1);
+        assert_eq!(&row[..], b"some text");
+    });
+    ramfs::delete("/shell/note");
+}
+
+#[test_case]
+fn test_complete_unique() {
+    // "cl", Tab, Enter
+    let scancodes = [0x2e, 0xae, 0x26, 0xa6, 0x0f, 0x8f, 0x1c, 0x9c];
+    for scancode in scancodes {
+        keyboard::add_scancode(scancode);
+    }
+    let line = vga_buffer::_input_with(&History::new(), &command_names());
+    assert_eq!(line.as_str(), "clear");
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_complete_list() {
+    // "c", Tab, Enter
+    let scancodes = [0x2e, 0xae, 0x0f, 0x8f, 0x1c, 0x9c];
+    for scancode in scancodes {
+        keyboard::add_scancode(scancode);
+    }
+    println!();
+    print!("> ");
+    let line = vga_buffer::_input_with(&History::new(), &command_names());
+    assert_eq!(line.as_str(), "c");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let listed = writer.read_row_trimmed(writer.row_position - 2);
+        assert_eq!(&listed[..], b"cat  clear  color  cpuinfo");
+        // The prompt and the line are repeated under the list
+        let prompt = writer.read_row_trimmed(writer.row_position - 1);
+        assert_eq!(&prompt[..], b"> c");
+    });
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_lockstats() {
+    println!();
+    execute("lockstats");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let row = writer.read_row_trimmed(writer.row_position - 1);
+        assert!(row.starts_with(b"WRITER: "));
+        assert!(row.ends_with(b" spins"));
+    });
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_reboot_disabled() {
+    println!();
+    execute("reboot");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let row = writer.read_row_trimmed(writer.row_position - 1);
+        assert_eq!(&row[..], b"reboot: not rebooting under test");
+    });
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_meminfo() {
+    println!();
+    execute("meminfo");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let row = writer.read_row_trimmed(writer.row_position - 1);
+        assert!(row.starts_with(b"heap: "));
+    });
+}
+
+#[test_case]
+fn test_execute_active_console() {
+    use spin::Mutex;
+    static RECORDER: Mutex<Recorder> = Mutex::new(Recorder::new());
+    console::set_active(&RECORDER);
+    execute("echo hi");
+    console::reset_active();
+    assert_eq!(RECORDER.lock().output, "hi\n");
+}
+
+#[test_case]
+fn test_serial_shell() {
+    while serial::read_byte().is_some() {}
+    for &byte in b"echo hi\r\n" {
+        serial::add_byte(byte);
+    }
+    let mut line = LineBuffer::default();
+    let mut out = Recorder::default();
+    while let Some(byte) = serial::read_byte() {
+        serve(&mut out, &mut line, byte).unwrap();
+    }
+    // The typed line is echoed back, then the output and the next prompt
+    assert_eq!(out.output, "echo hi\nhi\n> ");
+}
+
+#[test_case]
+fn test_line_buffer_backspace() {
+    let mut line = LineBuffer::default();
+    let mut out = Recorder::default();
+    for &byte in b"lz\x7fs" {
+        assert_eq!(line.feed(&mut out, byte), Ok(None));
+    }
+    assert_eq!(line.feed(&mut out, b'\n'), Ok(Some(String::from("ls"))));
+    assert_eq!(out.output, "lz\x08s\n");
+}
@@ -0,0 +1,45 @@
+use crate::pit::{sleep_ms, PIT_BASE_FREQUENCY};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+const CHANNEL_2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+// Bit 0 gates PIT channel 2, bit 1 connects its output to the speaker
+const SPEAKER_CONTROL: u16 = 0x61;
+
+// Programs channel 2 to a square wave of roughly `frequency` and returns the
+// divisor that was written. The speaker itself is left alone
+fn set_tone(frequency: u32) -> u16 {
+    let divisor = (PIT_BASE_FREQUENCY / frequency.max(1)).clamp(1, 0xFFFF) as u16;
+    let mut command_port: Port<u8> = Port::new(COMMAND);
+    let mut data_port: Port<u8> = Port::new(CHANNEL_2);
+    interrupts::without_interrupts(|| unsafe {
+        // Channel 2, low byte then high byte, mode 3 (square wave)
+        command_port.write(0xB6);
+        data_port.write((divisor & 0xFF) as u8);
+        data_port.write((divisor >> 8) as u8);
+    });
+    divisor
+}
+
+// Blocks for the whole duration, and needs interrupts enabled for sleep_ms
+pub fn beep(frequency: u32, duration_ms: u64) {
+    set_tone(frequency);
+    let mut control: Port<u8> = Port::new(SPEAKER_CONTROL);
+    let original = unsafe { control.read() };
+    unsafe {
+        control.write(original | 0x03);
+    }
+    sleep_ms(duration_ms);
+    unsafe {
+        control.write(original);
+    }
+}
+
+#[test_case]
+fn test_set_tone() {
+    assert_eq!(set_tone(440), (1193182 / 440) as u16);
+    assert_eq!(set_tone(1000), (1193182 / 1000) as u16);
+    // Out of range frequencies are clamped to what the divisor can hold
+    assert_eq!(set_tone(1), 0xFFFF);
+}
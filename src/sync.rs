@@ -0,0 +1,100 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::{Mutex, MutexGuard};
+
+// How often a lock was taken, and how many times callers had to go round
+// the loop because someone else held it
+#[derive(Debug)]
+pub struct LockStats {
+    acquisitions: AtomicU64,
+    spins: AtomicU64,
+}
+
+impl LockStats {
+    pub const fn new() -> Self {
+        LockStats {
+            acquisitions: AtomicU64::new(0),
+            spins: AtomicU64::new(0),
+        }
+    }
+    pub fn acquisitions(&self) -> u64 {
+        self.acquisitions.load(Ordering::Relaxed)
+    }
+    pub fn spins(&self) -> u64 {
+        self.spins.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for LockStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A spin::Mutex that records its use in a LockStats. The stats live in
+// their own static so they can be read while the lock is held, and without
+// going through a lazy_static
+pub struct InstrumentedMutex<T> {
+    inner: Mutex<T>,
+    stats: &'static LockStats,
+}
+
+impl<T> InstrumentedMutex<T> {
+    pub const fn new(value: T, stats: &'static LockStats) -> Self {
+        InstrumentedMutex {
+            inner: Mutex::new(value),
+            stats,
+        }
+    }
+
+    // Uncontended this is a single try_lock and one counter bump, the spin
+    // counter only moves while somebody else holds the lock
+    pub fn lock(&self) -> MutexGuard<T> {
+        self.stats.acquisitions.fetch_add(1, Ordering::Relaxed);
+        loop {
+            if let Some(guard) = self.inner.try_lock() {
+                return guard;
+            }
+            self.stats.spins.fetch_add(1, Ordering::Relaxed);
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        let guard = self.inner.try_lock();
+        if guard.is_some() {
+            self.stats.acquisitions.fetch_add(1, Ordering::Relaxed);
+        }
+        guard
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// # Safety
+    ///
+    /// Same as spin::Mutex::force_unlock, the current holder must never
+    /// touch the data again
+    pub unsafe fn force_unlock(&self) {
+        self.inner.force_unlock();
+    }
+
+    pub fn stats(&self) -> &'static LockStats {
+        self.stats
+    }
+}
+
+#[test_case]
+fn test_instrumented_mutex() {
+    static STATS: LockStats = LockStats::new();
+    static LOCK: InstrumentedMutex<u32> = InstrumentedMutex::new(0, &STATS);
+    *LOCK.lock() += 1;
+    {
+        let _held = LOCK.lock();
+        // A failed try_lock doesn't count as an acquisition
+        assert!(LOCK.try_lock().is_none());
+    }
+    assert_eq!(*LOCK.try_lock().unwrap(), 1);
+    assert_eq!(STATS.acquisitions(), 3);
+    assert_eq!(STATS.spins(), 0);
+}
@@ -0,0 +1,92 @@
+use crate::interrupts::ticks;
+use crate::pit::{self, PIT_BASE_FREQUENCY};
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// 0 until calibrate has run
+static CYCLES_PER_MS: AtomicU64 = AtomicU64::new(0);
+
+// Cycles since reset. Not serializing, so the CPU may reorder it with the
+// instructions around it by a few cycles
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+// Measures the TSC rate against the PIT over at least `ms`, longer gives a
+// more accurate rate. Needs interrupts to be enabled, like pit::sleep_ms
+pub fn calibrate(ms: u64) -> u64 {
+    // Starting right after a tick means whole ticks get counted
+    pit::sleep_ms(1);
+    let start_tick = ticks();
+    let start = rdtsc();
+    pit::sleep_ms(ms.max(1));
+    let cycles = u128::from(rdtsc() - start);
+    let elapsed_ticks = u128::from(ticks() - start_tick);
+    let elapsed_us =
+        elapsed_ticks * u128::from(pit::divisor()) * 1_000_000 / u128::from(PIT_BASE_FREQUENCY);
+    let cycles_per_ms = (cycles * 1000 / elapsed_us.max(1)) as u64;
+    CYCLES_PER_MS.store(cycles_per_ms, Ordering::Relaxed);
+    cycles_per_ms
+}
+
+pub fn cycles_per_ms() -> Option<u64> {
+    match CYCLES_PER_MS.load(Ordering::Relaxed) {
+        0 => None,
+        cycles => Some(cycles),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Stopwatch {
+    start: u64,
+}
+
+impl Stopwatch {
+    pub fn start() -> Stopwatch {
+        Stopwatch { start: rdtsc() }
+    }
+    pub fn elapsed_cycles(&self) -> u64 {
+        rdtsc().wrapping_sub(self.start)
+    }
+    // None until calibrate has been called
+    pub fn elapsed_ns(&self) -> Option<u64> {
+        let cycles_per_ms = cycles_per_ms()?;
+        let ns = u128::from(self.elapsed_cycles()) * 1_000_000 / u128::from(cycles_per_ms);
+        Some(ns as u64)
+    }
+}
+
+#[test_case]
+fn test_stopwatch() {
+    let stopwatch = Stopwatch::start();
+    let mut sum = 0u64;
+    for i in 0..10_000 {
+        // Volatile so the loop isn't optimized away
+        unsafe { core::ptr::write_volatile(&mut sum, sum + i) };
+    }
+    let first = stopwatch.elapsed_cycles();
+    let second = stopwatch.elapsed_cycles();
+    assert!(first > 0);
+    assert!(second >= first);
+}
+
+#[test_case]
+fn test_calibrate() {
+    let old_frequency = pit::frequency();
+    pit::set_frequency(1000);
+    let cycles_per_ms = calibrate(10);
+    assert!(cycles_per_ms > 0);
+    assert_eq!(self::cycles_per_ms(), Some(cycles_per_ms));
+    let stopwatch = Stopwatch::start();
+    pit::sleep_ms(5);
+    let elapsed = stopwatch.elapsed_ns().unwrap();
+    pit::set_frequency(old_frequency);
+    // Sleeping can start partway into a tick, so up to one 1ms tick short,
+    // and waking up can be a couple of ticks late. Anything further off
+    // means the calibration is wrong
+    assert!(
+        (4_000_000..=7_000_000).contains(&elapsed),
+        "slept {} ns",
+        elapsed
+    );
+}
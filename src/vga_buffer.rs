@@ -1,9 +1,20 @@
+#[cfg(feature = "alloc_scrollback")]
+use alloc::collections::VecDeque;
 use core::fmt;
+use core::ops::{Deref, DerefMut, Range};
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::console::{self, Console};
+use crate::keyboard;
+use crate::sync::{InstrumentedMutex, LockStats};
+use heapless::{Deque, String, Vec};
 use lazy_static::lazy_static;
+use pc_keyboard::{DecodedKey, KeyCode};
 use spin::Mutex;
 use volatile::Volatile;
 use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
 
 // Println and print macros
 #[macro_export]
@@ -17,26 +28,562 @@ macro_rules! println {
 	($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+#[macro_export]
+macro_rules! cprint {
+    ($color:expr, $($arg:tt)*) => ($crate::vga_buffer::_cprint($color, format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! cprintln {
+    ($color:expr) => ($crate::cprint!($color, "\n"));
+    ($color:expr, $($arg:tt)*) => ($crate::cprint!($color, "{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::vga_buffer::_eprint(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! clear {
+    () => {
+        $crate::vga_buffer::_clear()
+    };
+}
+
 #[macro_export]
 macro_rules! input {
     () => {
         $crate::vga_buffer::_input()
     };
+    ($history:expr) => {
+        $crate::vga_buffer::_input_with($history, &[])
+    };
+    ($history:expr, $completions:expr) => {
+        $crate::vga_buffer::_input_with($history, $completions)
+    };
+}
+
+// The kernel runs on a single CPU, so if WRITER is already locked here the
+// holder is whatever code we interrupted (or a caller further up our own
+// stack) and it can't continue until we return. Spinning would hang forever,
+// so the message is dropped instead and counted in DROPPED_MESSAGES
+static DROPPED_MESSAGES: AtomicUsize = AtomicUsize::new(0);
+
+pub fn dropped_messages() -> usize {
+    DROPPED_MESSAGES.load(Ordering::Relaxed)
+}
+
+// Printing and clear! take the lock here and nowhere else, exactly once and
+// with interrupts off, so checking input_mode and writing happen under the
+// same acquisition and no handler can get in between
+fn with_writer(f: impl FnOnce(&mut Writer)) {
+    interrupts::without_interrupts(|| match WRITER.try_lock() {
+        Some(mut writer) => {
+            // While a line or dialog is up, the switch waits for them
+            if !writer.input_mode {
+                apply_console_switch(&mut writer);
+            }
+            f(&mut writer)
+        }
+        None => {
+            DROPPED_MESSAGES.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+}
+
+// A console picked with console::set_active takes over from the default
+// below. Returns false when there isn't one. It may be locked by whoever we
+// interrupted as well, so it drops messages the same way with_writer does
+fn with_active(f: impl FnOnce(&mut dyn Console)) -> bool {
+    let active = match console::active() {
+        Some(active) => active,
+        None => return false,
+    };
+    interrupts::without_interrupts(|| match active.try_lock() {
+        Some(mut active) => f(&mut *active),
+        None => {
+            DROPPED_MESSAGES.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+    true
+}
+
+#[cfg(not(feature = "vga_text"))]
+pub fn _print(args: fmt::Arguments) {
+    if with_active(|active| active.write_fmt(args).unwrap()) {
+        return;
+    }
+    crate::serial::_print(args);
+}
+
+// Serial has no colors, the text is sent as is
+#[cfg(not(feature = "vga_text"))]
+pub fn _cprint(foreground: Color, args: fmt::Arguments) {
+    if with_active(|active| active.write_colored(foreground, args).unwrap()) {
+        return;
+    }
+    crate::serial::_print(args);
 }
 
+#[cfg(feature = "vga_text")]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    WRITER.lock().input_mode = false;
-    interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+    if with_active(|active| active.write_fmt(args).unwrap()) {
+        return;
+    }
+    with_writer(|writer| {
+        if writer.input_mode {
+            writer.queue_output(args);
+        } else {
+            writer.write_fmt(args).unwrap();
+        }
+    });
+}
+
+#[cfg(feature = "vga_text")]
+pub fn _cprint(foreground: Color, args: fmt::Arguments) {
+    use core::fmt::Write;
+    if with_active(|active| active.write_colored(foreground, args).unwrap()) {
+        return;
+    }
+    with_writer(|writer| {
+        if writer.input_mode {
+            writer.queue_output(args);
+            return;
+        }
+        // Only swap the foreground, and put the old color back afterwards so
+        // the global color isn't changed
+        let old_color = writer.color_code;
+        writer.color_code = old_color.with_foreground(foreground);
+        writer.write_fmt(args).unwrap();
+        writer.color_code = old_color;
+    });
+}
+
+/// Forces WRITER open so a panic handler can still print.
+///
+/// # Safety
+///
+/// Whoever held the lock may have been halfway through an update, and will
+/// race with us if it ever runs again. Only call this from a panic handler,
+/// with interrupts disabled, right before printing the panic message.
+/// Forcing it open when it isn't locked does nothing
+pub unsafe fn force_unlock() {
+    WRITER.force_unlock();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorSink {
+    Serial,
+    Vga,
+}
+
+// eprint! keeps diagnostics apart from normal output by sending them to COM1
+// when there is one. Otherwise they go on screen in red
+pub fn error_sink() -> ErrorSink {
+    if crate::serial::is_present() {
+        ErrorSink::Serial
+    } else {
+        ErrorSink::Vga
+    }
+}
+
+pub fn _eprint(args: fmt::Arguments) {
+    eprint_to(error_sink(), args);
+}
+
+fn eprint_to(sink: ErrorSink, args: fmt::Arguments) {
+    match sink {
+        ErrorSink::Serial => crate::serial::_print(args),
+        ErrorSink::Vga => _cprint(Color::Red, args),
+    }
+}
+
+pub fn _clear() {
+    if with_active(|active| active.clear()) {
+        return;
+    }
+    with_writer(|writer| {
+        // Clearing now would wipe the line being read, so it is queued like
+        // print! output, as the escape sequence that clears the screen
+        if writer.input_mode {
+            writer.queue_output(format_args!("\x1b[2J"));
+        } else {
+            writer.clear_screen();
+        }
     });
 }
 
-pub fn _input() {
-    println!("Hello World");
+// A line never wraps, it holds at most what fits between the column the
+// input started at and the end of that row. Once it is full further
+// characters are neither stored nor echoed, but editing keys and Enter still
+// work. The trailing newline is never part of the returned line
+pub type InputLine = String<BUFFER_WIDTH>;
+
+pub const HISTORY_SIZE: usize = 16;
+
+// The most recent lines entered, for Up and Down to recall. Empty lines and
+// repeats of the last line are not stored
+pub struct History {
+    lines: Deque<InputLine, HISTORY_SIZE>,
+}
+
+impl History {
+    pub const fn new() -> History {
+        History {
+            lines: Deque::new(),
+        }
+    }
+    pub fn push(&mut self, line: &str) {
+        if line.is_empty() || self.get(1) == Some(line) {
+            return;
+        }
+        if self.lines.is_full() {
+            self.lines.pop_front();
+        }
+        let mut entry = InputLine::new();
+        // An InputLine is never longer than BUFFER_WIDTH, so this fits
+        let _ = entry.push_str(line);
+        let _ = self.lines.push_back(entry);
+    }
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+    // 1 is the most recent line
+    pub fn get(&self, back: usize) -> Option<&str> {
+        let index = self.lines.len().checked_sub(back)?;
+        self.lines.iter().nth(index).map(|line| line.as_str())
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new()
+    }
+}
+
+// Output printed while a line is being read is held back and shown once the
+// line is finished, see Writer::queue_output
+pub fn _input() -> InputLine {
+    read_line_blocking(LineEditor::new)
+}
+
+// Tab completes the first word of the line against `completions`
+pub fn _input_with(history: &History, completions: &[&str]) -> InputLine {
+    read_line_blocking(|writer| {
+        LineEditor::new(writer)
+            .with_history(history)
+            .with_completions(completions)
+    })
+}
+
+// Like input!, but waits on the scancode stream so other tasks keep running.
+// Needs keyboard::init to have been called
+pub async fn read_line() -> InputLine {
+    read_line_async(LineEditor::new).await
+}
+
+pub async fn read_line_with(history: &History, completions: &[&str]) -> InputLine {
+    read_line_async(|writer| {
+        LineEditor::new(writer)
+            .with_history(history)
+            .with_completions(completions)
+    })
+    .await
+}
+
+fn read_line_blocking<'a>(new_editor: impl FnOnce(&mut Writer) -> LineEditor<'a>) -> InputLine {
+    let mut editor = interrupts::without_interrupts(|| new_editor(&mut WRITER.lock()));
+    loop {
+        if editor.feed(keyboard::read_key()) {
+            return editor.into_line();
+        }
+    }
+}
+
+async fn read_line_async<'a>(new_editor: impl FnOnce(&mut Writer) -> LineEditor<'a>) -> InputLine {
+    let mut scancodes = keyboard::ScancodeStream::new();
+    let mut editor = interrupts::without_interrupts(|| new_editor(&mut WRITER.lock()));
+    loop {
+        let key = keyboard::next_key(&mut scancodes).await;
+        if editor.feed(key) {
+            return editor.into_line();
+        }
+    }
+}
+
+struct LineEditor<'a> {
+    history: Option<&'a History>,
+    completions: &'a [&'a str],
+    // How far back in history the line came from, 0 is the line being typed
+    history_index: usize,
+    // The line being typed, kept while looking through history
+    draft: Vec<u8, BUFFER_WIDTH>,
+    line: Vec<u8, BUFFER_WIDTH>,
+    // Insertion index into line, separate from its length
+    cursor: usize,
+    row: usize,
+    col: usize,
+    width: usize,
+}
+
+impl<'a> LineEditor<'a> {
+    fn new(writer: &mut Writer) -> LineEditor<'a> {
+        // Leave room for the cursor after the last character
+        if writer.column_position >= writer.width - 1 {
+            writer.new_line();
+        }
+        writer.input_mode = true;
+        LineEditor {
+            history: None,
+            completions: &[],
+            history_index: 0,
+            draft: Vec::new(),
+            line: Vec::new(),
+            cursor: 0,
+            row: writer.row_position,
+            col: writer.column_position,
+            width: writer.width,
+        }
+    }
+    fn with_history(mut self, history: &'a History) -> LineEditor<'a> {
+        self.history = Some(history);
+        self
+    }
+    fn with_completions(mut self, completions: &'a [&'a str]) -> LineEditor<'a> {
+        self.completions = completions;
+        self
+    }
+    // Only the first word is completed, and only with the cursor at its end.
+    // A single match is filled in. With several, the part they all share is
+    // filled in, and if that adds nothing they are listed below the line
+    fn complete(&mut self, writer: &mut Writer) {
+        let typed = self.cursor;
+        if self.line.contains(&b' ') || typed != self.line.len() {
+            return;
+        }
+        let prefix = &self.line[..typed];
+        let mut matches = self
+            .completions
+            .iter()
+            .map(|word| word.as_bytes())
+            .filter(|word| word.starts_with(prefix));
+        let first = match matches.next() {
+            Some(first) => first,
+            None => return,
+        };
+        let mut common = first.len();
+        let mut several = false;
+        for word in matches {
+            several = true;
+            common = first
+                .iter()
+                .zip(word)
+                .take(common)
+                .take_while(|(a, b)| a == b)
+                .count();
+        }
+        if common > typed {
+            let len = common.min(self.max_len());
+            self.line = Vec::from_slice(&first[..len]).unwrap();
+            self.cursor = len;
+            self.redraw(writer, typed);
+        } else if several {
+            self.list_completions(writer);
+        }
+    }
+    // Prints the matches on their own line, then repeats whatever was in
+    // front of the input (the prompt) and the line itself below them
+    fn list_completions(&mut self, writer: &mut Writer) {
+        let prompt = writer.read_row(self.row);
+        writer.column_position = self.col + self.line.len();
+        writer.write_byte(b'\n');
+        let prefix = &self.line[..self.cursor];
+        for word in self.completions {
+            if word.as_bytes().starts_with(prefix) {
+                writer.write_string(word);
+                writer.write_string("  ");
+            }
+        }
+        writer.write_byte(b'\n');
+        self.reprint(writer, &prompt);
+    }
+    // Ctrl+L, moves the prompt and the line to the top of a cleared screen
+    fn clear_screen(&mut self, writer: &mut Writer) {
+        let prompt = writer.read_row(self.row);
+        writer.clear_screen();
+        self.reprint(writer, &prompt);
+    }
+    // Writes `prompt` (the row the line was on) up to where the line starts
+    // at the cursor, then the line after it
+    fn reprint(&mut self, writer: &mut Writer, prompt: &[u8; BUFFER_WIDTH]) {
+        writer.write_bytes(&prompt[..self.col]);
+        self.row = writer.row_position;
+        self.redraw(writer, 0);
+    }
+    fn recall(&mut self, writer: &mut Writer, index: usize) {
+        let history = match self.history {
+            Some(history) if index <= history.len() => history,
+            _ => return,
+        };
+        if self.history_index == 0 {
+            self.draft = self.line.clone();
+        }
+        self.history_index = index;
+        let new_line = match history.get(index) {
+            Some(line) => line.as_bytes(),
+            None => &self.draft[..],
+        };
+        let len = new_line.len().min(self.max_len());
+        let new_line = Vec::from_slice(&new_line[..len]).unwrap();
+        self.replace_line(writer, new_line);
+    }
+    // Swaps in a whole new line with the cursor at its end
+    fn replace_line(&mut self, writer: &mut Writer, new_line: Vec<u8, BUFFER_WIDTH>) {
+        let old_len = self.line.len();
+        self.line = new_line;
+        self.cursor = self.line.len();
+        self.redraw(writer, 0);
+        // Blank whatever is left of a longer previous line
+        for col in self.line.len()..old_len {
+            writer.write_at(self.row, self.col + col, " ", writer.color_code);
+        }
+    }
+    fn max_len(&self) -> usize {
+        self.width - 1 - self.col
+    }
+    // Handles one key with the writer locked, and ends input mode once the
+    // line is finished. Returns true then
+    fn feed(&mut self, key: DecodedKey) -> bool {
+        interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let done = self.handle_key(&mut writer, key);
+            // row and col point into the console the line started on, so a
+            // switch waits for the line like it does in with_writer
+            if done {
+                writer.end_input();
+                apply_console_switch(&mut writer);
+            }
+            done
+        })
+    }
+    // Returns true once the line is finished
+    fn handle_key(&mut self, writer: &mut Writer, key: DecodedKey) -> bool {
+        // PageUp and PageDown move through scrollback a screen at a time, any
+        // other key goes back to the live screen before it is handled
+        match key {
+            DecodedKey::RawKey(KeyCode::PageUp) => {
+                writer.scroll_back(writer.text_rows());
+                return false;
+            }
+            DecodedKey::RawKey(KeyCode::PageDown) => {
+                writer.scroll_forward(writer.text_rows());
+                return false;
+            }
+            _ => writer.scroll_to_bottom(),
+        }
+        match key {
+            DecodedKey::Unicode('\n') => {
+                writer.column_position = self.col + self.line.len();
+                writer.write_byte(b'\n');
+                return true;
+            }
+            // Ctrl+C gives up on the line, nothing typed so far is returned
+            DecodedKey::Unicode('\u{3}') => {
+                writer.column_position = self.col + self.line.len();
+                writer.write_string("^C\n");
+                self.line.clear();
+                return true;
+            }
+            // Ctrl+U
+            DecodedKey::Unicode('\u{15}') => self.replace_line(writer, Vec::new()),
+            DecodedKey::Unicode('\u{8}') => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                    self.redraw(writer, self.cursor);
+                }
+            }
+            DecodedKey::Unicode('\u{7f}') | DecodedKey::RawKey(KeyCode::Delete) => {
+                if self.cursor < self.line.len() {
+                    self.line.remove(self.cursor);
+                    self.redraw(writer, self.cursor);
+                }
+            }
+            DecodedKey::Unicode(character @ ' '..='~')
+                if !writer.insert_mode && self.cursor < self.line.len() =>
+            {
+                self.line[self.cursor] = character as u8;
+                self.cursor += 1;
+                self.redraw(writer, self.cursor - 1);
+            }
+            DecodedKey::Unicode(character @ ' '..='~') => {
+                if self.line.len() < self.max_len() {
+                    let _ = self.line.insert(self.cursor, character as u8);
+                    self.cursor += 1;
+                    self.redraw(writer, self.cursor - 1);
+                }
+            }
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => self.cursor = self.cursor.saturating_sub(1),
+            DecodedKey::RawKey(KeyCode::ArrowRight) => {
+                self.cursor = (self.cursor + 1).min(self.line.len())
+            }
+            DecodedKey::RawKey(KeyCode::Insert) => {
+                writer.insert_mode = !writer.insert_mode;
+                // Thin underline cursor when inserting, full block when
+                // overwriting
+                if writer.insert_mode {
+                    writer.enable_cursor(14, 15);
+                } else {
+                    writer.enable_cursor(0, 15);
+                }
+            }
+            DecodedKey::Unicode('\t') => self.complete(writer),
+            DecodedKey::Unicode('\u{c}') => self.clear_screen(writer),
+            DecodedKey::RawKey(KeyCode::ArrowUp) => self.recall(writer, self.history_index + 1),
+            DecodedKey::RawKey(KeyCode::ArrowDown) => {
+                if self.history_index > 0 {
+                    self.recall(writer, self.history_index - 1);
+                }
+            }
+            DecodedKey::RawKey(KeyCode::Home) => self.cursor = 0,
+            DecodedKey::RawKey(KeyCode::End) => self.cursor = self.line.len(),
+            _ => {}
+        }
+        writer.row_position = self.row;
+        writer.column_position = self.col + self.cursor;
+        writer.update_cursor();
+        false
+    }
+    // Repaint the line from `from` onwards, blanking the cell a removed
+    // character leaves behind
+    fn redraw(&self, writer: &mut Writer, from: usize) {
+        let color = writer.color_code;
+        let tail = core::str::from_utf8(&self.line[from..]).unwrap();
+        writer.write_at(self.row, self.col + from, tail, color);
+        writer.write_at(self.row, self.col + self.line.len(), " ", color);
+    }
+    fn into_line(self) -> InputLine {
+        let mut line = String::new();
+        // Only printable ASCII is ever stored, so this is valid UTF-8 and fits
+        line.push_str(core::str::from_utf8(&self.line).unwrap())
+            .unwrap();
+        line
+    }
 }
 
 #[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Color {
     Black = 0,
@@ -57,173 +604,2579 @@ pub enum Color {
     White = 15,
 }
 
-#[derive(Clone, Copy)]
+// Maps the 8 ANSI color indices onto their VGA equivalents
+fn ansi_color(index: u16) -> Color {
+    match index {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Brown,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        _ => Color::LightGray,
+    }
+}
+
+// The RGB values the standard VGA palette shows each color as
+const PALETTE: [(Color, [u8; 3]); 16] = [
+    (Color::Black, [0, 0, 0]),
+    (Color::Blue, [0, 0, 170]),
+    (Color::Green, [0, 170, 0]),
+    (Color::Cyan, [0, 170, 170]),
+    (Color::Red, [170, 0, 0]),
+    (Color::Magenta, [170, 0, 170]),
+    (Color::Brown, [170, 85, 0]),
+    (Color::LightGray, [170, 170, 170]),
+    (Color::DarkGray, [85, 85, 85]),
+    (Color::LightBlue, [85, 85, 255]),
+    (Color::LightGreen, [85, 255, 85]),
+    (Color::LightCyan, [85, 255, 255]),
+    (Color::LightRed, [255, 85, 85]),
+    (Color::Pink, [255, 85, 255]),
+    (Color::Yellow, [255, 255, 85]),
+    (Color::White, [255, 255, 255]),
+];
+
+impl Color {
+    // Closest palette entry by straight-line distance in RGB. Mid gray
+    // (128, 128, 128) lands on LightGray, which is 42 away per channel
+    // against DarkGray's 43. Ties go to the lower color number
+    pub fn nearest_from_rgb(r: u8, g: u8, b: u8) -> Color {
+        let distance = |rgb: [u8; 3]| -> u32 {
+            [r, g, b]
+                .iter()
+                .zip(rgb)
+                .map(|(&a, b)| u32::from(a.abs_diff(b)).pow(2))
+                .sum()
+        };
+        PALETTE
+            .iter()
+            .min_by_key(|(_, rgb)| distance(*rgb))
+            .map(|&(color, _)| color)
+            .unwrap()
+    }
+}
+
+// Shading glyphs and how much of the cell each covers, in quarters
+const SHADES: [(u8, u32); 4] = [(0xB0, 1), (0xB1, 2), (0xB2, 3), (0xDB, 4)];
+const GRAYS: [(Color, u32); 3] = [
+    (Color::DarkGray, 85),
+    (Color::LightGray, 170),
+    (Color::White, 255),
+];
+
+// Picks the glyph and gray that, drawn on black, come closest to
+// `brightness`. 0 is a blank cell. On a tie the darker gray with the denser
+// glyph wins
+pub fn shade(brightness: u8) -> (u8, Color) {
+    let target = u32::from(brightness) * 4;
+    let mut best = (b' ', Color::Black);
+    let mut best_error = target;
+    for (color, level) in GRAYS {
+        for (glyph, quarters) in SHADES {
+            let error = target.abs_diff(quarters * level);
+            if error < best_error {
+                best = (glyph, color);
+                best_error = error;
+            }
+        }
+    }
+    best
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ColorCode(u8);
 
 impl ColorCode {
-    fn new(foreground: Color, background: Color) -> ColorCode {
+    pub const fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
+    fn with_foreground(self, foreground: Color) -> ColorCode {
+        ColorCode((self.0 & 0xf0) | (foreground as u8))
+    }
+    fn with_background(self, background: Color) -> ColorCode {
+        ColorCode((background as u8) << 4 | (self.0 & 0x0f))
+    }
+    // Sets bit 7, which only blinks once Writer::set_blink(true) is on.
+    // Otherwise it selects the bright version of the background color
+    pub fn blink(self) -> ColorCode {
+        ColorCode(self.0 | 0x80)
+    }
 }
 
-#[derive(Clone, Copy)]
+const DEFAULT_COLOR_CODE: ColorCode = ColorCode::new(Color::White, Color::Black);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct ScreenChar {
     pub ascii_character: u8,
     color_code: ColorCode,
 }
 
+impl ScreenChar {
+    pub const fn new(ascii_character: u8, color_code: ColorCode) -> ScreenChar {
+        ScreenChar {
+            ascii_character,
+            color_code,
+        }
+    }
+    pub const fn blank(color_code: ColorCode) -> ScreenChar {
+        ScreenChar::new(b' ', color_code)
+    }
+}
+
 pub const BUFFER_HEIGHT: usize = 25;
 pub const BUFFER_WIDTH: usize = 80;
+pub const TAB_WIDTH: usize = 4;
+const MAX_ANSI_PARAMS: usize = 4;
+pub const SCROLLBACK_LINES: usize = 200;
+const PENDING_OUTPUT_SIZE: usize = 512;
+pub const CURSOR_STACK_DEPTH: usize = 8;
+// Text columns inside a dialog border, and how many body lines it can show
+const DIALOG_MAX_WIDTH: usize = 50;
+const DIALOG_MAX_LINES: usize = 12;
+const DIALOG_COLOR: ColorCode = ColorCode::new(Color::White, Color::Blue);
+const DIALOG_OK: &str = "[OK]";
+pub const CONSOLE_COUNT: usize = 4;
+
+const BLANK_CHAR: ScreenChar = ScreenChar::blank(DEFAULT_COLOR_CODE);
 
 pub struct Buffer {
     pub chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
-pub struct Writer {
-    pub column_position: usize,
-    pub row_position: usize,
-    color_code: ColorCode,
-    pub buffer: &'static mut Buffer,
-    pub input_mode: bool,
-}
+type Row = [ScreenChar; BUFFER_WIDTH];
 
-impl fmt::Write for Writer {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.write_string(s);
-        Ok(())
-    }
+// Rows that scrolled off the top of the screen, at most `limit` of them.
+// Once full the oldest row is dropped. Without the alloc_scrollback feature
+// they live in a fixed ring, so the limit can't go above SCROLLBACK_LINES
+#[cfg(not(feature = "alloc_scrollback"))]
+struct Scrollback {
+    rows: [Row; SCROLLBACK_LINES],
+    start: usize,
+    len: usize,
+    limit: usize,
 }
 
-impl Writer {
-    fn write_byte(&mut self, byte: u8) {
-        match byte {
-            b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
-                }
-                let row = self.row_position;
-                let col = self.column_position;
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
-                self.column_position += 1;
-            }
+#[cfg(not(feature = "alloc_scrollback"))]
+impl Scrollback {
+    const fn new() -> Scrollback {
+        Scrollback {
+            rows: [[BLANK_CHAR; BUFFER_WIDTH]; SCROLLBACK_LINES],
+            start: 0,
+            len: 0,
+            limit: SCROLLBACK_LINES,
         }
     }
-    fn backspace(&mut self) {
-        if self.row_position == 0 && self.column_position == 0 {
-            return;
+    fn len(&self) -> usize {
+        self.len
+    }
+    fn set_limit(&mut self, lines: usize) {
+        self.limit = lines.min(SCROLLBACK_LINES);
+        if self.len > self.limit {
+            self.start = (self.start + self.len - self.limit) % SCROLLBACK_LINES;
+            self.len = self.limit;
         }
-        if self.column_position == 0 {
-            self.row_position -= 1;
-            self.column_position = self.get_last_col(self.row_position);
+    }
+    fn push(&mut self, row: Row) {
+        if self.limit == 0 {
             return;
         }
-        // Set char at that row and col to blank (space)
-        // Push back col position so next char will overwrite that char
-        let blank = ScreenChar {
-            ascii_character: 0x00,
-            color_code: self.color_code,
-        };
-        self.buffer.chars[self.row_position][self.column_position - 1].write(blank);
-        self.column_position -= 1;
-    }
-    pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                0x08 => self.backspace(),
-                _ => self.write_byte(0xfe),
-            }
+        let index = (self.start + self.len) % SCROLLBACK_LINES;
+        self.rows[index] = row;
+        if self.len < self.limit {
+            self.len += 1;
+        } else {
+            self.start = (self.start + 1) % SCROLLBACK_LINES;
         }
     }
-    fn new_line(&mut self) {
-        if self.row_position == (BUFFER_HEIGHT - 1) {
-            self.shift_up();
-            self.clear_row(BUFFER_HEIGHT - 1);
-        } else {
-            self.row_position += 1;
+    // Index 0 is the oldest row kept
+    fn get(&self, index: usize) -> &Row {
+        &self.rows[(self.start + index) % SCROLLBACK_LINES]
+    }
+    // The ring is already there
+    fn init(&mut self) {}
+}
+
+// Heap backed, so the limit can be anything. push runs with WRITER locked,
+// often from interrupt handlers, so it must never allocate: room for `limit`
+// rows is reserved up front by init and set_limit instead. WRITER is used
+// before the heap is set up, so until init_scrollback is called rows that
+// scroll off are simply not kept. set_scrollback_limit allocates too and
+// must not be called before allocator::init_heap
+#[cfg(feature = "alloc_scrollback")]
+struct Scrollback {
+    rows: Option<VecDeque<Row>>,
+    limit: usize,
+}
+
+#[cfg(feature = "alloc_scrollback")]
+impl Scrollback {
+    const fn new() -> Scrollback {
+        Scrollback {
+            rows: None,
+            limit: SCROLLBACK_LINES,
         }
-        self.column_position = 0;
     }
-    fn shift_up(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                if !(row <= 0) {
-                    self.buffer.chars[row - 1][col].write(character);
-                }
-            }
+    fn len(&self) -> usize {
+        self.rows.as_ref().map_or(0, VecDeque::len)
+    }
+    fn set_limit(&mut self, lines: usize) {
+        self.limit = lines;
+        let rows = self.rows.get_or_insert_with(VecDeque::new);
+        while rows.len() > lines {
+            rows.pop_front();
         }
+        rows.shrink_to(lines);
+        rows.reserve_exact(lines - rows.len());
     }
-    fn clear_row(&mut self, row: usize) {
-        let blank = ScreenChar {
-            ascii_character: b' ',
-            color_code: self.color_code,
+    fn push(&mut self, row: Row) {
+        let rows = match &mut self.rows {
+            Some(rows) if self.limit > 0 => rows,
+            _ => return,
         };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
+        if rows.len() >= self.limit {
+            rows.pop_front();
         }
+        rows.push_back(row);
     }
-    fn get_last_col(&mut self, row: usize) -> usize {
-        // let mut col: usize = 0;
-        // let mut char;
-        // for i in 0..BUFFER_WIDTH {
-        //     char = self.buffer.chars[row][i].read();
-        //     if !char.ascii_character == 0x00 {
-        //         col += 1;
-        //     }
-        // }
-        let mut col: usize = 0; // 0
-        let mut char = self.buffer.chars[row][0].read();
-        while char.ascii_character != 0x00 {
-            // 0x00
-            col += 1; // col -> 2
-            char = self.buffer.chars[row][col].read(); // char -> char at col 2
-        }
-        return col;
+    // Index 0 is the oldest row kept
+    fn get(&self, index: usize) -> &Row {
+        &self.rows.as_ref().unwrap()[index]
+    }
+    fn init(&mut self) {
+        self.set_limit(self.limit);
     }
 }
 
-lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        row_position: 0,
-        color_code: ColorCode::new(Color::White, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-        input_mode: false
-    });
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DialogResult {
+    Ok,
+    Cancel,
 }
 
-// TESTS
-#[test_case]
-fn test_print_one() {
-    print!("Hello World");
+// The first `chars` characters of `s`
+fn truncate(s: &str, chars: usize) -> &str {
+    s.char_indices()
+        .nth(chars)
+        .map_or(s, |(index, _)| &s[..index])
 }
 
-#[test_case]
-fn test_print_many() {
-    for _ in 1..100 {
-        print!("Hello World");
+// Greedy word wrap into lines of at most `width` characters. Words that
+// don't fit on a line of their own are split, lines past the limit dropped
+fn wrap_text(text: &str, width: usize) -> Vec<&str, DIALOG_MAX_LINES> {
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut rest = paragraph.trim();
+        if rest.is_empty() && lines.push("").is_err() {
+            break;
+        }
+        while !rest.is_empty() {
+            let limit = truncate(rest, width).len();
+            let end = if limit == rest.len() || rest[limit..].starts_with(' ') {
+                limit
+            } else {
+                rest[..limit]
+                    .rfind(' ')
+                    .filter(|&end| end > 0)
+                    .unwrap_or(limit)
+            };
+            if lines.push(rest[..end].trim_end()).is_err() {
+                return lines;
+            }
+            rest = rest[end..].trim_start();
+        }
     }
+    lines
 }
 
-#[test_case]
-fn test_print_output() {
-    interrupts::without_interrupts(|| {
-        println!(); // Make sure no text is on the same line from previous tests
-        let printed_str = "Some random text";
-        print!("{}", printed_str);
-        let writer = WRITER.lock();
-        let row_position = writer.row_position;
-        for (i, c) in printed_str.chars().enumerate() {
-            let screen_char = writer.buffer.chars[row_position][i].read();
-            let ascii_char = char::from(screen_char.ascii_character);
-            assert_eq!(ascii_char, c);
-        }
-    })
+pub struct Writer {
+    pub column_position: usize,
+    pub row_position: usize,
+    color_code: ColorCode,
+    pub buffer: &'static mut Buffer,
+    // Set while _input is reading a line. print! output is queued in
+    // pending_output meanwhile, so it can't land in the middle of the line
+    pub input_mode: bool,
+    // Whether typing in the middle of an input line inserts or overwrites.
+    // Toggled with the Insert key and kept between lines
+    pub insert_mode: bool,
+    pending_output: String<PENDING_OUTPUT_SIZE>,
+    cursor_stack: Vec<(usize, usize), CURSOR_STACK_DEPTH>,
+    ansi: AnsiParser,
+    word_wrap: bool,
+    scrollback: Scrollback,
+    // How many rows the view is scrolled back, 0 shows the live screen. The
+    // live screen is kept in live_screen while scrolled back
+    view_offset: usize,
+    live_screen: [Row; BUFFER_HEIGHT],
+    // The bottom row is reserved for set_status while this is on
+    status_bar: bool,
+    // Every write lands in back_buffer first. With auto_flush on it is also
+    // written through to the screen, otherwise only flush updates the screen
+    back_buffer: [Row; BUFFER_HEIGHT],
+    auto_flush: bool,
+    // Size of the area in use, at most BUFFER_WIDTH by BUFFER_HEIGHT. The
+    // hardware buffer is always the full size, smaller sizes are for tests
+    width: usize,
+    height: usize,
+}
+
+// The glyphs the VGA font has for bytes 0x80 to 0xfe, in order. 0xff is a
+// non-breaking space and left out
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■";
+
+fn cp437_from_char(c: char) -> Option<u8> {
+    if c.is_ascii() {
+        return Some(c as u8);
+    }
+    CP437_HIGH
+        .chars()
+        .position(|glyph| glyph == c)
+        .map(|index| 0x80 + index as u8)
+}
+
+fn is_word_byte(byte: u8) -> bool {
+    matches!(byte, 0x21..=0x7e)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnsiState {
+    Normal,
+    Escape,
+    Csi,
+}
+
+// Tracks a partially received escape sequence. It lives on the Writer so a
+// sequence split across several write_str calls is still recognized
+struct AnsiParser {
+    state: AnsiState,
+    params: [u16; MAX_ANSI_PARAMS],
+    param_count: usize,
+}
+
+impl AnsiParser {
+    const fn new() -> AnsiParser {
+        AnsiParser {
+            state: AnsiState::Normal,
+            params: [0; MAX_ANSI_PARAMS],
+            param_count: 0,
+        }
+    }
+}
+
+// Puts the previous color back when dropped. It holds the only mutable
+// borrow of the writer for as long as it lives, so all writing in the scope
+// has to go through the guard (it derefs to Writer), not the writer itself:
+//
+//     let mut writer = writer.with_color(Color::Red, Color::Black);
+//     writer.write_string("error");
+pub struct ColorGuard<'a> {
+    writer: &'a mut Writer,
+    old_color: ColorCode,
+}
+
+impl Deref for ColorGuard<'_> {
+    type Target = Writer;
+
+    fn deref(&self) -> &Writer {
+        self.writer
+    }
+}
+
+impl DerefMut for ColorGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Writer {
+        self.writer
+    }
+}
+
+impl Drop for ColorGuard<'_> {
+    fn drop(&mut self) {
+        self.writer.color_code = self.old_color;
+    }
+}
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+impl Console for Writer {
+    fn clear(&mut self) {
+        self.clear_screen();
+    }
+    fn set_color(&mut self, foreground: Color, background: Color) {
+        Writer::set_color(self, foreground, background);
+    }
+    fn write_colored(&mut self, foreground: Color, args: fmt::Arguments) -> fmt::Result {
+        // Keeps the background, like cprint!
+        let old_color = self.color_code;
+        self.color_code = old_color.with_foreground(foreground);
+        let result = fmt::Write::write_fmt(self, args);
+        self.color_code = old_color;
+        result
+    }
+}
+
+impl Writer {
+    pub fn new(buffer: &'static mut Buffer) -> Writer {
+        Writer::with_size(buffer, BUFFER_WIDTH, BUFFER_HEIGHT)
+    }
+    pub fn with_size(buffer: &'static mut Buffer, width: usize, height: usize) -> Writer {
+        assert!(width > 0 && width <= BUFFER_WIDTH);
+        assert!(height > 1 && height <= BUFFER_HEIGHT);
+        Writer {
+            column_position: 0,
+            row_position: 0,
+            color_code: DEFAULT_COLOR_CODE,
+            buffer,
+            input_mode: false,
+            insert_mode: true,
+            pending_output: String::new(),
+            cursor_stack: Vec::new(),
+            ansi: AnsiParser::new(),
+            word_wrap: false,
+            scrollback: Scrollback::new(),
+            view_offset: 0,
+            live_screen: [[BLANK_CHAR; BUFFER_WIDTH]; BUFFER_HEIGHT],
+            status_bar: false,
+            back_buffer: [[BLANK_CHAR; BUFFER_WIDTH]; BUFFER_HEIGHT],
+            auto_flush: true,
+            width,
+            height,
+        }
+    }
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color_code = ColorCode::new(foreground, background);
+    }
+    pub fn color(&self) -> ColorCode {
+        self.color_code
+    }
+    // Columns and rows available to text, the status bar row isn't counted
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.text_rows())
+    }
+    pub fn with_color(&mut self, foreground: Color, background: Color) -> ColorGuard<'_> {
+        let old_color = self.color_code;
+        self.set_color(foreground, background);
+        ColorGuard {
+            writer: self,
+            old_color,
+        }
+    }
+    fn write_byte(&mut self, byte: u8) {
+        self.scroll_to_bottom();
+        self.clamp_position();
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.column_position = 0,
+            b'\t' => {
+                let spaces = TAB_WIDTH - self.column_position % TAB_WIDTH;
+                if self.column_position + spaces > self.width {
+                    self.new_line();
+                } else {
+                    for _ in 0..spaces {
+                        self.write_byte(b' ');
+                    }
+                }
+            }
+            byte => {
+                if self.column_position >= self.width {
+                    self.new_line();
+                }
+                let row = self.row_position;
+                let col = self.column_position;
+                self.set_cell(row, col, ScreenChar::new(byte, self.color_code));
+                self.column_position += 1;
+            }
+        }
+        self.update_cursor();
+    }
+    fn backspace(&mut self) {
+        self.clamp_position();
+        if self.row_position == 0 && self.column_position == 0 {
+            return;
+        }
+        if self.column_position == 0 {
+            self.row_position -= 1;
+            self.column_position = self.get_last_col(self.row_position);
+        } else {
+            // Set char at that row and col to blank (space)
+            // Push back col position so next char will overwrite that char
+            let blank = ScreenChar::blank(self.color_code);
+            self.set_cell(self.row_position, self.column_position - 1, blank);
+            self.column_position -= 1;
+        }
+        self.update_cursor();
+    }
+    // Colors are not kept, queued text is shown in the current color. Output
+    // that doesn't fit is dropped and counted like a message dropped for a
+    // held lock
+    fn queue_output(&mut self, args: fmt::Arguments) {
+        use core::fmt::Write;
+        if self.pending_output.write_fmt(args).is_err() {
+            DROPPED_MESSAGES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    fn end_input(&mut self) {
+        self.input_mode = false;
+        let pending = core::mem::take(&mut self.pending_output);
+        self.write_string(&pending);
+    }
+    pub fn set_word_wrap(&mut self, on: bool) {
+        self.word_wrap = on;
+    }
+    pub fn write_string(&mut self, s: &str) {
+        self.scroll_to_bottom();
+        let bytes = s.as_bytes();
+        let mut in_word = false;
+        for (i, c) in s.char_indices() {
+            let byte = cp437_from_char(c).unwrap_or(0xfe);
+            self.dispatch_byte(byte, &bytes[i..], &mut in_word);
+        }
+    }
+    // Like write_string, but each byte is taken as a CP437 character as is,
+    // so the input doesn't have to be UTF-8
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.scroll_to_bottom();
+        let mut in_word = false;
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.dispatch_byte(byte, &bytes[i..], &mut in_word);
+        }
+    }
+    // `rest` starts at this byte and is only used to look ahead for word wrap
+    fn dispatch_byte(&mut self, byte: u8, rest: &[u8], in_word: &mut bool) {
+        if byte == 0x1b || self.ansi.state != AnsiState::Normal {
+            self.process_ansi(byte);
+            return;
+        }
+        if self.word_wrap && is_word_byte(byte) && !*in_word {
+            self.wrap_word(rest);
+        }
+        *in_word = is_word_byte(byte);
+        match byte {
+            0x20..=0x7e | 0x80..=0xfe | b'\n' | b'\r' | b'\t' => self.write_byte(byte),
+            0x08 => self.backspace(),
+            _ => self.write_byte(0xfe),
+        }
+    }
+    pub fn write_char(&mut self, c: char) {
+        self.write_string(c.encode_utf8(&mut [0; 4]));
+    }
+    // Called at the start of each word. Moves to a new line first if the
+    // word doesn't fit on the current one. Words longer than a whole row
+    // still hard wrap. Only the rest of the current string is looked at, so a
+    // word split across two writes is wrapped as two separate words
+    fn wrap_word(&mut self, rest: &[u8]) {
+        let len = rest.iter().take_while(|&&byte| is_word_byte(byte)).count();
+        if len <= self.width && self.column_position + len > self.width {
+            self.new_line();
+        }
+    }
+    fn process_ansi(&mut self, byte: u8) {
+        match self.ansi.state {
+            AnsiState::Normal => {
+                self.ansi = AnsiParser::new();
+                self.ansi.state = AnsiState::Escape;
+            }
+            AnsiState::Escape => {
+                // Only CSI (ESC [) sequences are supported, drop anything else
+                self.ansi.state = match byte {
+                    b'[' => AnsiState::Csi,
+                    _ => AnsiState::Normal,
+                };
+            }
+            AnsiState::Csi => match byte {
+                b'0'..=b'9' => {
+                    if self.ansi.param_count == 0 {
+                        self.ansi.param_count = 1;
+                    }
+                    let param = &mut self.ansi.params[self.ansi.param_count - 1];
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add(u16::from(byte - b'0'));
+                }
+                b';' => {
+                    if self.ansi.param_count == 0 {
+                        self.ansi.param_count = 1;
+                    }
+                    if self.ansi.param_count < MAX_ANSI_PARAMS {
+                        self.ansi.param_count += 1;
+                    }
+                }
+                0x40..=0x7e => {
+                    self.ansi.state = AnsiState::Normal;
+                    self.dispatch_ansi(byte);
+                }
+                // Intermediate and private bytes are ignored
+                _ => {}
+            },
+        }
+    }
+    fn dispatch_ansi(&mut self, command: u8) {
+        let params = self.ansi.params;
+        let params = &params[..self.ansi.param_count];
+        // Positions and counts default to 1, and 0 means 1 as well
+        let param_or_one = |index: usize| match params.get(index) {
+            Some(&param) if param > 0 => usize::from(param),
+            _ => 1,
+        };
+        let last_row = self.text_rows() - 1;
+        let last_col = self.width - 1;
+        match command {
+            b'm' => {
+                // An empty SGR sequence is the same as a reset
+                if params.is_empty() {
+                    self.color_code = DEFAULT_COLOR_CODE;
+                }
+                for &param in params {
+                    match param {
+                        0 => self.color_code = DEFAULT_COLOR_CODE,
+                        30..=37 => {
+                            self.color_code =
+                                self.color_code.with_foreground(ansi_color(param - 30))
+                        }
+                        40..=47 => {
+                            self.color_code =
+                                self.color_code.with_background(ansi_color(param - 40))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            // Cursor movement, 1 based and clamped to the text area
+            b'H' | b'f' => {
+                self.row_position = (param_or_one(0) - 1).min(last_row);
+                self.column_position = (param_or_one(1) - 1).min(last_col);
+            }
+            b'A' => self.row_position = self.row_position.saturating_sub(param_or_one(0)),
+            b'B' => self.row_position = (self.row_position + param_or_one(0)).min(last_row),
+            b'C' => self.column_position = (self.column_position + param_or_one(0)).min(last_col),
+            b'D' => self.column_position = self.column_position.saturating_sub(param_or_one(0)),
+            // Only the whole screen form of erase is supported
+            b'J' if params == [2] => self.clear_screen(),
+            _ => {}
+        }
+    }
+    pub fn write_at(&mut self, row: usize, col: usize, s: &str, color: ColorCode) {
+        if row >= self.height || col >= self.width {
+            return;
+        }
+        // Anything past the end of the row is dropped, this never wraps or
+        // moves the cursor
+        for (i, c) in s.chars().take(self.width - col).enumerate() {
+            let ascii_character = match cp437_from_char(c) {
+                Some(byte @ (0x20..=0x7e | 0x80..=0xfe)) => byte,
+                _ => 0xfe,
+            };
+            self.set_cell(row, col + i, ScreenChar::new(ascii_character, color));
+        }
+    }
+    // Rows available to normal output, the status bar row is not included
+    fn text_rows(&self) -> usize {
+        if self.status_bar {
+            self.height - 1
+        } else {
+            self.height
+        }
+    }
+    // Strings wider than the screen start at column 0 and are truncated by
+    // write_at
+    pub fn write_centered(&mut self, row: usize, s: &str, color: ColorCode) {
+        let col = self.width.saturating_sub(s.len()) / 2;
+        self.write_at(row, col, s, color);
+    }
+    pub fn write_right(&mut self, row: usize, s: &str, color: ColorCode) {
+        let col = self.width.saturating_sub(s.len());
+        self.write_at(row, col, s, color);
+    }
+    // Draws [####----] across the whole row, the cursor is left where it is
+    pub fn draw_progress(&mut self, row: usize, fraction: f32, color: ColorCode) {
+        if row >= self.height {
+            return;
+        }
+        let inner_width = self.width - 2;
+        let filled = (fraction.clamp(0.0, 1.0) * inner_width as f32) as usize;
+        let mut cells: Vec<ScreenChar, BUFFER_WIDTH> = Vec::new();
+        let _ = cells.push(ScreenChar::new(b'[', color));
+        for col in 0..inner_width {
+            let ch = if col < filled { b'#' } else { b'-' };
+            let _ = cells.push(ScreenChar::new(ch, color));
+        }
+        let _ = cells.push(ScreenChar::new(b']', color));
+        self.write_block(&cells, row, 0);
+    }
+    // Lays the cells out from (start_row, start_col) on, carrying on at the
+    // start of the next row at the right edge. Whatever runs past the last
+    // row is dropped. The screen is updated in one go at the end
+    pub fn write_block(&mut self, cells: &[ScreenChar], start_row: usize, start_col: usize) {
+        if start_row >= self.height || start_col >= self.width {
+            return;
+        }
+        let mut rest = cells;
+        let mut row = start_row;
+        let mut col = start_col;
+        while !rest.is_empty() && row < self.height {
+            let len = rest.len().min(self.width - col);
+            self.back_buffer[row][col..col + len].copy_from_slice(&rest[..len]);
+            rest = &rest[len..];
+            row += 1;
+            col = 0;
+        }
+        if self.auto_flush {
+            self.flush_rows(start_row..row);
+        }
+    }
+    // One cell of grayscale, for bar charts and the like. See shade
+    pub fn write_shaded(&mut self, row: usize, col: usize, brightness: u8) {
+        let (glyph, color) = shade(brightness);
+        self.put_char(row, col, glyph, ColorCode::new(color, Color::Black));
+    }
+    // Out of bounds cells are skipped so shapes clip at the screen edges
+    fn put_char(&mut self, row: usize, col: usize, ascii_character: u8, color: ColorCode) {
+        if row < self.height && col < self.width {
+            self.set_cell(row, col, ScreenChar::new(ascii_character, color));
+        }
+    }
+    pub fn fill_rect(
+        &mut self,
+        row: usize,
+        col: usize,
+        height: usize,
+        width: usize,
+        ch: u8,
+        color: ColorCode,
+    ) {
+        let row_end = row.saturating_add(height).min(self.height);
+        let col_end = col.saturating_add(width).min(self.width);
+        for r in row..row_end {
+            for c in col..col_end {
+                self.put_char(r, c, ch, color);
+            }
+        }
+    }
+    // Draws a double line border with CP437 box drawing characters, the
+    // inside is left untouched
+    pub fn draw_box(
+        &mut self,
+        row: usize,
+        col: usize,
+        height: usize,
+        width: usize,
+        color: ColorCode,
+    ) {
+        // A box starting off screen has nothing to draw, and returning here
+        // keeps row + 1 and col + 1 below from overflowing
+        if height == 0 || width == 0 || row >= self.height || col >= self.width {
+            return;
+        }
+        let bottom = row.saturating_add(height - 1);
+        let right = col.saturating_add(width - 1);
+        for c in col + 1..right {
+            self.put_char(row, c, 0xCD, color);
+            self.put_char(bottom, c, 0xCD, color);
+        }
+        for r in row + 1..bottom {
+            self.put_char(r, col, 0xBA, color);
+            self.put_char(r, right, 0xBA, color);
+        }
+        self.put_char(row, col, 0xC9, color);
+        self.put_char(row, right, 0xBB, color);
+        self.put_char(bottom, col, 0xC8, color);
+        self.put_char(bottom, right, 0xBC, color);
+    }
+    fn new_line(&mut self) {
+        let last_row = self.text_rows() - 1;
+        if self.row_position >= last_row {
+            self.shift_up();
+            self.clear_row(last_row);
+            self.row_position = last_row;
+        } else {
+            self.row_position += 1;
+        }
+        self.column_position = 0;
+        self.update_cursor();
+    }
+    fn shift_up(&mut self) {
+        // Move every row up by one. The bottom row is left as is, callers
+        // are responsible for clearing it. The top row goes into scrollback
+        let top = self.read_cells(0);
+        self.scrollback.push(top);
+        let text_rows = self.text_rows();
+        self.back_buffer.copy_within(1..text_rows, 0);
+        if self.auto_flush {
+            self.flush_rows(0..text_rows - 1);
+        }
+    }
+    fn clear_row(&mut self, row: usize) {
+        let blank = ScreenChar::blank(self.color_code);
+        for col in 0..self.width {
+            self.set_cell(row, col, blank);
+        }
+    }
+    pub fn clear_screen(&mut self) {
+        for row in 0..self.text_rows() {
+            self.clear_row(row);
+        }
+        self.row_position = 0;
+        self.column_position = 0;
+        self.update_cursor();
+    }
+    // Up to CURSOR_STACK_DEPTH positions are kept. Pushing onto a full stack
+    // drops the oldest one, so the innermost saves always survive
+    pub fn push_cursor(&mut self) {
+        if self.cursor_stack.is_full() {
+            self.cursor_stack.remove(0);
+        }
+        let _ = self
+            .cursor_stack
+            .push((self.row_position, self.column_position));
+    }
+    // Returns the restored position, or None if nothing was saved
+    pub fn pop_cursor(&mut self) -> Option<(usize, usize)> {
+        let (row, col) = self.cursor_stack.pop()?;
+        self.row_position = row;
+        self.column_position = col;
+        self.update_cursor();
+        Some((row, col))
+    }
+    fn update_cursor(&mut self) {
+        // Without vga_text there may be no VGA card behind these ports, and
+        // the buffer isn't shown anyway. The same goes for the cursor and
+        // blink settings below
+        if !cfg!(feature = "vga_text") {
+            return;
+        }
+        // The CRTC takes the cursor location as a linear cell offset, split
+        // into a high (0x0E) and low (0x0F) byte
+        let position = (self.row_position * BUFFER_WIDTH + self.column_position) as u16;
+        let mut index_port: Port<u8> = Port::new(0x3D4);
+        let mut data_port: Port<u8> = Port::new(0x3D5);
+        unsafe {
+            index_port.write(0x0F);
+            data_port.write((position & 0xFF) as u8);
+            index_port.write(0x0E);
+            data_port.write((position >> 8) as u8);
+        }
+    }
+    pub fn enable_cursor(&mut self, start_scanline: u8, end_scanline: u8) {
+        if !cfg!(feature = "vga_text") {
+            return;
+        }
+        let mut index_port: Port<u8> = Port::new(0x3D4);
+        let mut data_port: Port<u8> = Port::new(0x3D5);
+        // Keep the reserved upper bits of the cursor start/end registers
+        unsafe {
+            index_port.write(0x0A);
+            let start = data_port.read();
+            data_port.write((start & 0xC0) | start_scanline);
+            index_port.write(0x0B);
+            let end = data_port.read();
+            data_port.write((end & 0xE0) | end_scanline);
+        }
+        self.update_cursor();
+    }
+    // Bit 7 of the attribute byte is shared: with blink on it makes text
+    // blink, but backgrounds are limited to the 8 dark colors. With blink off
+    // all 16 background colors can be used
+    pub fn set_blink(&mut self, on: bool) {
+        if !cfg!(feature = "vga_text") {
+            return;
+        }
+        let mut input_status: Port<u8> = Port::new(0x3DA);
+        let mut attribute_port: Port<u8> = Port::new(0x3C0);
+        let mut attribute_read: Port<u8> = Port::new(0x3C1);
+        unsafe {
+            // Reading the input status register resets the attribute
+            // controller to expect an index. Index 0x10 is the mode control
+            // register, 0x20 keeps the display enabled
+            input_status.read();
+            attribute_port.write(0x30);
+            let mode = attribute_read.read();
+            let mode = if on { mode | 0x08 } else { mode & !0x08 };
+            attribute_port.write(mode);
+        }
+    }
+    pub fn disable_cursor(&mut self) {
+        if !cfg!(feature = "vga_text") {
+            return;
+        }
+        let mut index_port: Port<u8> = Port::new(0x3D4);
+        let mut data_port: Port<u8> = Port::new(0x3D5);
+        // Bit 5 of the cursor start register turns the cursor off. The rest
+        // of the register is kept, like enable_cursor does
+        unsafe {
+            index_port.write(0x0A);
+            let start = data_port.read();
+            data_port.write(start | 0x20);
+        }
+    }
+    // row_position and column_position are pub, so they may have been set
+    // past the text area. Treat a row below it as a write to the last row,
+    // scrolling everything up once, and a column too far right as the end of
+    // the row
+    fn clamp_position(&mut self) {
+        let last_row = self.text_rows() - 1;
+        if self.row_position > last_row {
+            self.row_position = last_row;
+            self.shift_up();
+            self.clear_row(last_row);
+        }
+        self.column_position = self.column_position.min(self.width);
+    }
+    fn check_cell(&self, row: usize, col: usize) {
+        debug_assert!(
+            row < self.height && col < self.width,
+            "cell ({}, {}) is outside the {}x{} buffer",
+            row,
+            col,
+            self.width,
+            self.height
+        );
+    }
+    fn cell(&self, row: usize, col: usize) -> ScreenChar {
+        self.check_cell(row, col);
+        self.back_buffer[row][col]
+    }
+    fn set_cell(&mut self, row: usize, col: usize, screen_char: ScreenChar) {
+        self.check_cell(row, col);
+        self.back_buffer[row][col] = screen_char;
+        if self.auto_flush {
+            self.buffer.chars[row][col].write(screen_char);
+        }
+    }
+    pub fn set_auto_flush(&mut self, on: bool) {
+        self.auto_flush = on;
+        if on {
+            self.flush();
+        }
+    }
+    // Copies the whole back buffer to the screen
+    pub fn flush(&mut self) {
+        self.flush_rows(0..self.height);
+    }
+    // Copies rows from the back buffer to the screen, a whole row per
+    // volatile write when it spans the buffer, otherwise cell by cell so
+    // nothing past width is touched
+    fn flush_rows(&mut self, rows: Range<usize>) {
+        let rows = rows.start..rows.end.min(self.height);
+        let screen = self.buffer.chars.as_mut_ptr() as *mut Row;
+        for row in rows {
+            // Volatile<ScreenChar> is repr(transparent), so a screen row has
+            // the same layout as a Row, and row is below BUFFER_HEIGHT
+            unsafe {
+                if self.width == BUFFER_WIDTH {
+                    ptr::write_volatile(screen.add(row), self.back_buffer[row]);
+                } else {
+                    let cells = screen.add(row) as *mut ScreenChar;
+                    for col in 0..self.width {
+                        ptr::write_volatile(cells.add(col), self.back_buffer[row][col]);
+                    }
+                }
+            }
+        }
+    }
+    // Everything on screen, status bar included, for putting back with
+    // restore once a popup is done. It's a 4000 byte array, so mind the stack
+    pub fn snapshot(&self) -> [Row; BUFFER_HEIGHT] {
+        self.back_buffer
+    }
+    // Only the cells are restored, the cursor and color stay as they are
+    pub fn restore(&mut self, snapshot: &[Row; BUFFER_HEIGHT]) {
+        self.back_buffer = *snapshot;
+        if self.auto_flush {
+            self.flush();
+        }
+    }
+    // Boot splash: clears the screen and centers the lines in a double line
+    // box. Lines too wide for the screen are cut short, and lines that don't
+    // fit below each other are left out
+    pub fn print_centered_box(&mut self, lines: &[&str], color: ColorCode) {
+        self.clear_screen();
+        let text_rows = self.text_rows();
+        // A border and a blank row or column of padding on every side
+        let max_width = self.width.saturating_sub(4);
+        let shown = lines.len().min(text_rows.saturating_sub(4));
+        let lines = &lines[..shown];
+        let text_width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0)
+            .min(max_width);
+        let width = text_width + 4;
+        let height = lines.len() + 4;
+        let row = (text_rows - height.min(text_rows)) / 2;
+        let col = (self.width - width.min(self.width)) / 2;
+        self.draw_box(row, col, height, width, color);
+        for (i, line) in lines.iter().enumerate() {
+            self.write_centered(row + 2 + i, truncate(line, text_width), color);
+        }
+    }
+    // Draws a centered box with the title in its top border, the body
+    // wrapped inside it and an OK prompt at the bottom. Nothing is drawn on
+    // screens too small to fit a border, padding and some text
+    pub fn draw_dialog(&mut self, title: &str, body: &str) {
+        let text_rows = self.text_rows();
+        if self.width < 8 || text_rows < 4 {
+            return;
+        }
+        let max_width = DIALOG_MAX_WIDTH.min(self.width - 4);
+        let lines = wrap_text(body, max_width);
+        let text_width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .chain([title.chars().count() + 2, DIALOG_OK.len()])
+            .max()
+            .unwrap()
+            .min(max_width);
+        // A border and a column of padding on both sides
+        let width = text_width + 4;
+        // Border, body, a blank row, the OK prompt and the bottom border
+        let height = (lines.len() + 4).min(text_rows);
+        let row = (text_rows - height) / 2;
+        let col = (self.width - width) / 2;
+        self.fill_rect(row, col, height, width, b' ', DIALOG_COLOR);
+        self.draw_box(row, col, height, width, DIALOG_COLOR);
+        // The title sits in the top border with a space either side
+        let title = truncate(title, text_width.saturating_sub(2));
+        let title_col = col + (width - title.chars().count()) / 2;
+        self.write_at(row, title_col - 1, " ", DIALOG_COLOR);
+        self.write_at(row, title_col, title, DIALOG_COLOR);
+        self.write_at(row, title_col + title.chars().count(), " ", DIALOG_COLOR);
+        for (i, line) in lines.iter().take(height - 4).enumerate() {
+            self.write_at(row + 1 + i, col + 2, line, DIALOG_COLOR);
+        }
+        let ok_col = col + (width - DIALOG_OK.len()) / 2;
+        self.write_at(row + height - 2, ok_col, DIALOG_OK, DIALOG_COLOR);
+    }
+    // Whole rows are copied, including any columns past width, which are
+    // always blank
+    fn read_cells(&self, row: usize) -> Row {
+        self.back_buffer[row]
+    }
+    pub fn read_row(&self, row: usize) -> [u8; BUFFER_WIDTH] {
+        let mut text = [b' '; BUFFER_WIDTH];
+        for (byte, screen_char) in text.iter_mut().zip(self.back_buffer[row].iter()) {
+            *byte = screen_char.ascii_character;
+        }
+        text
+    }
+    // Trailing blanks are dropped, an empty row gives an empty Vec
+    pub fn read_row_trimmed(&self, row: usize) -> Vec<u8, BUFFER_WIDTH> {
+        let text = self.read_row(row);
+        let len = text
+            .iter()
+            .rposition(|&byte| byte != b' ')
+            .map_or(0, |col| col + 1);
+        Vec::from_slice(&text[..len]).unwrap()
+    }
+    fn write_row(&mut self, row: usize, chars: &Row) {
+        for (col, &screen_char) in chars.iter().take(self.width).enumerate() {
+            self.set_cell(row, col, screen_char);
+        }
+    }
+    pub fn enable_status_bar(&mut self) {
+        if self.status_bar {
+            return;
+        }
+        // Make room if the cursor is on the row being taken over
+        if self.row_position == self.height - 1 {
+            self.shift_up();
+            self.row_position -= 1;
+        }
+        self.status_bar = true;
+        self.clear_row(self.height - 1);
+        self.update_cursor();
+    }
+    pub fn disable_status_bar(&mut self) {
+        if !self.status_bar {
+            return;
+        }
+        self.status_bar = false;
+        self.clear_row(self.height - 1);
+    }
+    // Enables the status bar if needed. The rest of the row is blanked in
+    // the same color
+    pub fn set_status(&mut self, s: &str, color: ColorCode) {
+        self.enable_status_bar();
+        let blank = ScreenChar::blank(color);
+        for col in 0..self.width {
+            self.set_cell(self.height - 1, col, blank);
+        }
+        self.write_at(self.height - 1, 0, s, color);
+    }
+    // How many rows that scrolled off the top are kept, SCROLLBACK_LINES by
+    // default. Lowering it drops the oldest rows. Without alloc_scrollback
+    // it can't be raised above SCROLLBACK_LINES, with it this allocates, so
+    // it needs the heap
+    pub fn set_scrollback_limit(&mut self, lines: usize) {
+        self.scroll_to_bottom();
+        self.scrollback.set_limit(lines);
+    }
+    pub fn scroll_back(&mut self, lines: usize) {
+        if self.view_offset == 0 {
+            for row in 0..self.height {
+                self.live_screen[row] = self.read_cells(row);
+            }
+        }
+        self.view_offset = (self.view_offset + lines).min(self.scrollback.len());
+        self.repaint_view();
+    }
+    pub fn scroll_forward(&mut self, lines: usize) {
+        if self.view_offset == 0 {
+            return;
+        }
+        self.view_offset = self.view_offset.saturating_sub(lines);
+        self.repaint_view();
+    }
+    fn scroll_to_bottom(&mut self) {
+        if self.view_offset != 0 {
+            self.scroll_forward(self.view_offset);
+        }
+    }
+    // History and the live screen form one long list of rows, the view shows
+    // text_rows of them ending view_offset rows above the bottom
+    fn repaint_view(&mut self) {
+        let first = self.scrollback.len() - self.view_offset;
+        for row in 0..self.text_rows() {
+            let line = first + row;
+            let chars = if line < self.scrollback.len() {
+                *self.scrollback.get(line)
+            } else {
+                self.live_screen[line - self.scrollback.len()]
+            };
+            self.write_row(row, &chars);
+        }
+    }
+    // Scrollback and the status bar are shared by every console, so only the
+    // text rows, the cursor and the color are swapped
+    fn save_console(&mut self, console: &mut SavedConsole) {
+        self.scroll_to_bottom();
+        let text_rows = self.text_rows();
+        console.cells[..text_rows].copy_from_slice(&self.back_buffer[..text_rows]);
+        console.row_position = self.row_position;
+        console.column_position = self.column_position;
+        console.color_code = self.color_code;
+    }
+    fn load_console(&mut self, console: &SavedConsole) {
+        let text_rows = self.text_rows();
+        self.back_buffer[..text_rows].copy_from_slice(&console.cells[..text_rows]);
+        if self.auto_flush {
+            self.flush_rows(0..text_rows);
+        }
+        self.row_position = console.row_position.min(text_rows - 1);
+        self.column_position = console.column_position;
+        self.color_code = console.color_code;
+        self.update_cursor();
+    }
+    fn get_last_col(&self, row: usize) -> usize {
+        // Land just past the last non-blank cell. A full row has no free
+        // cell, so land on the last one instead
+        let col = (0..self.width)
+            .rev()
+            .find(|&col| self.cell(row, col).ascii_character != b' ')
+            .map_or(0, |col| col + 1);
+        col.min(self.width - 1)
+    }
+}
+
+#[cfg(feature = "vga_text")]
+fn text_buffer() -> &'static mut Buffer {
+    unsafe { &mut *(0xb8000 as *mut Buffer) }
+}
+
+// Without VGA text mode 0xb8000 may not be mapped to anything sensible, so
+// WRITER draws into ordinary memory instead. Code that uses it directly
+// keeps working, it just isn't visible. print! goes to serial, see _print
+#[cfg(not(feature = "vga_text"))]
+fn text_buffer() -> &'static mut Buffer {
+    static mut FALLBACK_BUFFER: [[u16; BUFFER_WIDTH]; BUFFER_HEIGHT] =
+        [[0; BUFFER_WIDTH]; BUFFER_HEIGHT];
+    // Only called once, from the WRITER initializer. Volatile is
+    // transparent and ScreenChar is two bytes, so the layouts match
+    unsafe { &mut *(core::ptr::addr_of_mut!(FALLBACK_BUFFER) as *mut Buffer) }
+}
+
+// Counts for WRITER, for chasing contention on it. See the lockstats command
+pub static WRITER_STATS: LockStats = LockStats::new();
+
+lazy_static! {
+    pub static ref WRITER: InstrumentedMutex<Writer> =
+        InstrumentedMutex::new(Writer::new(text_buffer()), &WRITER_STATS);
+}
+
+// Needs the heap with alloc_scrollback, so this runs after
+// allocator::init_heap. Rows that scroll off before that aren't kept
+pub fn init_scrollback() {
+    interrupts::without_interrupts(|| WRITER.lock().scrollback.init());
+}
+
+// A console that isn't on screen. WRITER always works on the one that is,
+// so print! goes to whichever console is in front
+#[derive(Clone, Copy)]
+struct SavedConsole {
+    cells: [Row; BUFFER_HEIGHT],
+    row_position: usize,
+    column_position: usize,
+    color_code: ColorCode,
+}
+
+impl SavedConsole {
+    const fn new() -> SavedConsole {
+        SavedConsole {
+            cells: [[BLANK_CHAR; BUFFER_WIDTH]; BUFFER_HEIGHT],
+            row_position: 0,
+            column_position: 0,
+            color_code: DEFAULT_COLOR_CODE,
+        }
+    }
+}
+
+struct Consoles {
+    active: usize,
+    // The entry for the active console is stale until it's switched away from
+    saved: [SavedConsole; CONSOLE_COUNT],
+}
+
+// Always locked after WRITER, never the other way around
+static CONSOLES: Mutex<Consoles> = Mutex::new(Consoles {
+    active: 0,
+    saved: [SavedConsole::new(); CONSOLE_COUNT],
+});
+
+pub fn active_console() -> usize {
+    interrupts::without_interrupts(|| CONSOLES.lock().active)
+}
+
+// Puts console `n` on screen, keeping the current one's contents and cursor
+// for later. Numbers past CONSOLE_COUNT are ignored
+pub fn switch_console(n: usize) {
+    interrupts::without_interrupts(|| switch_console_locked(&mut WRITER.lock(), n));
+}
+
+fn switch_console_locked(writer: &mut Writer, n: usize) {
+    if n >= CONSOLE_COUNT {
+        return;
+    }
+    let mut consoles = CONSOLES.lock();
+    let active = consoles.active;
+    if n == active {
+        return;
+    }
+    writer.save_console(&mut consoles.saved[active]);
+    writer.load_console(&consoles.saved[n]);
+    consoles.active = n;
+}
+
+const NO_SWITCH: usize = usize::MAX;
+
+// Set by Alt+F1 and so on from the keyboard decoder, which may run while
+// WRITER is held and so can't switch itself. The line editor applies it
+// once the line is done, print! on its next call, and show_dialog once it
+// closes
+static PENDING_CONSOLE: AtomicUsize = AtomicUsize::new(NO_SWITCH);
+
+pub fn request_console_switch(n: usize) {
+    PENDING_CONSOLE.store(n, Ordering::Relaxed);
+}
+
+fn apply_console_switch(writer: &mut Writer) {
+    let n = PENDING_CONSOLE.swap(NO_SWITCH, Ordering::Relaxed);
+    if n != NO_SWITCH {
+        switch_console_locked(writer, n);
+    }
+}
+
+// Draws a dialog and waits for Enter (Ok) or Esc (Cancel), then puts the
+// screen back. WRITER is only held for the drawing and the restore, not
+// while waiting for the key. Output printed meanwhile is held back like it
+// is during input, and shows up once the dialog is gone
+pub fn show_dialog(title: &str, body: &str) -> DialogResult {
+    let (snapshot, was_input) = interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let snapshot = writer.snapshot();
+        writer.draw_dialog(title, body);
+        (snapshot, core::mem::replace(&mut writer.input_mode, true))
+    });
+    let result = loop {
+        match keyboard::read_key() {
+            DecodedKey::Unicode('\n') => break DialogResult::Ok,
+            DecodedKey::Unicode('\u{1b}') | DecodedKey::RawKey(KeyCode::Escape) => {
+                break DialogResult::Cancel
+            }
+            _ => {}
+        }
+    };
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.restore(&snapshot);
+        // A line being read when the dialog came up gets its output once
+        // it's done, as it would have anyway
+        if !was_input {
+            writer.end_input();
+            apply_console_switch(&mut writer);
+        }
+    });
+    result
+}
+
+// TESTS
+#[test_case]
+fn test_print_one() {
+    print!("Hello World");
+}
+
+#[test_case]
+fn test_print_many() {
+    for _ in 1..100 {
+        print!("Hello World");
+    }
+}
+
+// Compares a row, without its trailing blanks, against `expected`. Locks
+// WRITER itself, so don't call it with the lock held
+#[cfg(all(test, feature = "vga_text"))]
+#[track_caller]
+fn assert_row_eq(row: usize, expected: &str) {
+    let actual = interrupts::without_interrupts(|| WRITER.lock().read_row_trimmed(row));
+    let actual: alloc::string::String = actual.iter().map(|&byte| char::from(byte)).collect();
+    assert!(
+        actual == expected,
+        "row {} is {:?}, expected {:?}",
+        row,
+        actual,
+        expected
+    );
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_print_output() {
+    interrupts::without_interrupts(|| {
+        println!(); // Make sure no text is on the same line from previous tests
+        let printed_str = "Some random text";
+        print!("{}", printed_str);
+        let row = WRITER.lock().row_position;
+        assert_row_eq(row, printed_str);
+    })
+}
+
+#[test_case]
+fn test_eprintln_serial() {
+    // The tests talk to the host over COM1, so it is always there
+    assert_eq!(error_sink(), ErrorSink::Serial);
+    let output = crate::serial::capture(|| eprintln!("oops {}", 1));
+    assert_eq!(output, "oops 1\n");
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_eprint() {
+    println!();
+    eprint_to(ErrorSink::Vga, format_args!("oops"));
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let red = writer.color_code.with_foreground(Color::Red);
+        for (col, byte) in b"oops".iter().enumerate() {
+            let cell = writer.cell(writer.row_position, col);
+            assert_eq!(cell, ScreenChar::new(*byte, red));
+        }
+    });
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_writer_stats() {
+    let before = WRITER_STATS.acquisitions();
+    print!("counted");
+    assert!(WRITER_STATS.acquisitions() > before);
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_print_wrapped() {
+    interrupts::without_interrupts(|| {
+        println!();
+        let line = "0123456789".repeat(BUFFER_WIDTH / 10);
+        print!("{}past the edge", line);
+        let row = WRITER.lock().row_position;
+        assert_row_eq(row - 1, &line);
+        assert_row_eq(row, "past the edge");
+    })
+}
+
+#[test_case]
+fn test_shift_up() {
+    use core::fmt::Write;
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        for i in 0..BUFFER_HEIGHT {
+            writeln!(writer, "Line {}", i).unwrap();
+        }
+        let mut second_row = [0u8; BUFFER_WIDTH];
+        for (col, byte) in second_row.iter_mut().enumerate() {
+            *byte = writer.buffer.chars[1][col].read().ascii_character;
+        }
+        writer.new_line();
+        for (col, byte) in second_row.iter().enumerate() {
+            assert_eq!(writer.buffer.chars[0][col].read().ascii_character, *byte);
+        }
+    })
+}
+
+#[test_case]
+fn test_shift_up_matches_cell_copy() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        for row in 0..writer.text_rows() {
+            for col in 0..BUFFER_WIDTH {
+                let color = ColorCode::new(Color::Cyan, Color::Black);
+                writer.put_char(row, col, (row * 7 + col) as u8, color);
+            }
+        }
+        // What the old one cell at a time loop produced
+        let mut expected = writer.snapshot();
+        for row in 1..writer.text_rows() {
+            for col in 0..BUFFER_WIDTH {
+                expected[row - 1][col] = expected[row][col];
+            }
+        }
+        writer.shift_up();
+        assert!(writer.snapshot() == expected);
+        for (row, chars) in expected.iter().enumerate() {
+            for (col, &screen_char) in chars.iter().enumerate() {
+                assert_eq!(writer.buffer.chars[row][col].read(), screen_char);
+            }
+        }
+        writer.clear_screen();
+    })
+}
+
+#[test_case]
+fn test_set_color() {
+    use core::fmt::Write;
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let old_color = writer.color();
+        writeln!(writer).unwrap();
+        writer.set_color(Color::Red, Color::Black);
+        writer.write_string("X");
+        let row = writer.row_position;
+        let screen_char = writer.buffer.chars[row][0].read();
+        assert_eq!(
+            screen_char.color_code,
+            ColorCode::new(Color::Red, Color::Black)
+        );
+        assert_eq!(writer.color(), ColorCode::new(Color::Red, Color::Black));
+        writer.color_code = old_color;
+    })
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_cprint() {
+    interrupts::without_interrupts(|| {
+        let old_color = WRITER.lock().color();
+        println!();
+        cprint!(Color::Green, "G");
+        let writer = WRITER.lock();
+        let screen_char = writer.buffer.chars[writer.row_position][0].read();
+        assert_eq!(char::from(screen_char.ascii_character), 'G');
+        assert_eq!(
+            screen_char.color_code,
+            old_color.with_foreground(Color::Green)
+        );
+        assert_eq!(writer.color(), old_color);
+    })
+}
+
+#[test_case]
+fn test_clear_screen() {
+    interrupts::without_interrupts(|| {
+        print!("Some text to clear");
+        clear!();
+        let writer = WRITER.lock();
+        for row in 0..BUFFER_HEIGHT {
+            for col in 0..BUFFER_WIDTH {
+                let screen_char = writer.buffer.chars[row][col].read();
+                assert_eq!(screen_char.ascii_character, b' ');
+            }
+        }
+        assert_eq!(writer.row_position, 0);
+        assert_eq!(writer.column_position, 0);
+    })
+}
+
+#[cfg(test)]
+fn fill_row(writer: &mut Writer, row: usize, len: usize) {
+    for col in 0..BUFFER_WIDTH {
+        let ascii_character = if col < len { b'a' } else { b' ' };
+        writer.set_cell(
+            row,
+            col,
+            ScreenChar::new(ascii_character, writer.color_code),
+        );
+    }
+}
+
+#[test_case]
+fn test_get_last_col_empty_row() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        fill_row(&mut writer, 0, 0);
+        assert_eq!(writer.get_last_col(0), 0);
+    })
+}
+
+#[test_case]
+fn test_get_last_col_half_full_row() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        fill_row(&mut writer, 0, BUFFER_WIDTH / 2);
+        assert_eq!(writer.get_last_col(0), BUFFER_WIDTH / 2);
+    })
+}
+
+#[test_case]
+fn test_get_last_col_full_row() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        fill_row(&mut writer, 0, BUFFER_WIDTH);
+        assert_eq!(writer.get_last_col(0), BUFFER_WIDTH - 1);
+    })
+}
+
+#[test_case]
+fn test_backspace_across_newline() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.write_string("\n");
+        let row = writer.row_position;
+        writer.clear_row(row);
+        writer.write_string("abc\n");
+        let expected_row = writer.row_position - 1;
+        writer.write_string("\x08");
+        assert_eq!(writer.row_position, expected_row);
+        assert_eq!(writer.column_position, 3);
+    })
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_tab() {
+    interrupts::without_interrupts(|| {
+        println!();
+        print!("a\tb");
+        let writer = WRITER.lock();
+        let row = writer.row_position;
+        let screen_char = writer.buffer.chars[row][TAB_WIDTH].read();
+        assert_eq!(char::from(screen_char.ascii_character), 'b');
+        assert_eq!(writer.buffer.chars[row][1].read().ascii_character, b' ');
+    })
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_carriage_return() {
+    interrupts::without_interrupts(|| {
+        println!();
+        print!("abc\rX");
+        let writer = WRITER.lock();
+        let row = writer.row_position;
+        for (i, c) in "Xbc".chars().enumerate() {
+            let screen_char = writer.buffer.chars[row][i].read();
+            assert_eq!(char::from(screen_char.ascii_character), c);
+        }
+        assert_eq!(writer.column_position, 1);
+    })
+}
+
+#[test_case]
+fn test_write_at() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let position = (writer.row_position, writer.column_position);
+        let color = writer.color();
+        writer.write_at(0, BUFFER_WIDTH - 2, "OK", color);
+        assert_eq!(
+            writer.buffer.chars[0][BUFFER_WIDTH - 2]
+                .read()
+                .ascii_character,
+            b'O'
+        );
+        assert_eq!(
+            writer.buffer.chars[0][BUFFER_WIDTH - 1]
+                .read()
+                .ascii_character,
+            b'K'
+        );
+        assert_eq!((writer.row_position, writer.column_position), position);
+    })
+}
+
+#[test_case]
+fn test_save_restore_cursor() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.row_position = 3;
+        writer.column_position = 5;
+        writer.push_cursor();
+        let color = writer.color();
+        writer.write_at(10, 10, "overlay", color);
+        writer.row_position = 10;
+        writer.column_position = 17;
+        writer.pop_cursor();
+        assert_eq!((writer.row_position, writer.column_position), (3, 5));
+    })
+}
+
+#[test_case]
+fn test_cursor_stack() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let positions = [(1, 2), (3, 4), (5, 6)];
+        for (row, col) in positions {
+            writer.row_position = row;
+            writer.column_position = col;
+            writer.push_cursor();
+        }
+        for &position in positions.iter().rev() {
+            assert_eq!(writer.pop_cursor(), Some(position));
+            assert_eq!((writer.row_position, writer.column_position), position);
+        }
+        assert_eq!(writer.pop_cursor(), None);
+    })
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_ansi_color() {
+    interrupts::without_interrupts(|| {
+        println!();
+        print!("\x1b[31mred\x1b[0mx");
+        let writer = WRITER.lock();
+        let row = writer.row_position;
+        let red = DEFAULT_COLOR_CODE.with_foreground(Color::Red);
+        for (i, c) in "red".chars().enumerate() {
+            let screen_char = writer.buffer.chars[row][i].read();
+            assert_eq!(char::from(screen_char.ascii_character), c);
+            assert_eq!(screen_char.color_code, red);
+        }
+        let screen_char = writer.buffer.chars[row][3].read();
+        assert_eq!(char::from(screen_char.ascii_character), 'x');
+        assert_eq!(screen_char.color_code, DEFAULT_COLOR_CODE);
+    })
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_ansi_cursor_position() {
+    interrupts::without_interrupts(|| {
+        print!("\x1b[1;1HX");
+        assert_eq!(
+            WRITER.lock().buffer.chars[0][0].read().ascii_character,
+            b'X'
+        );
+        // Out of range positions are clamped to the bottom right
+        print!("\x1b[99;200H");
+        let mut writer = WRITER.lock();
+        assert_eq!(
+            (writer.row_position, writer.column_position),
+            (BUFFER_HEIGHT - 1, BUFFER_WIDTH - 1)
+        );
+        writer.write_string("\x1b[5;10H\x1b[2A\x1b[B\x1b[3C\x1b[D");
+        assert_eq!((writer.row_position, writer.column_position), (3, 11));
+        writer.write_string("\x1b[0A\x1b[20D");
+        assert_eq!((writer.row_position, writer.column_position), (2, 0));
+    })
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_ansi_clear_screen() {
+    interrupts::without_interrupts(|| {
+        print!("\x1b[2J");
+        let writer = WRITER.lock();
+        assert_eq!((writer.row_position, writer.column_position), (0, 0));
+        for row in 0..BUFFER_HEIGHT {
+            assert!(writer.read_row_trimmed(row).is_empty());
+        }
+    })
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_ansi_unknown_sequence() {
+    interrupts::without_interrupts(|| {
+        println!();
+        print!("\x1b[5zok");
+        let writer = WRITER.lock();
+        let row = writer.row_position;
+        assert_eq!(writer.buffer.chars[row][0].read().ascii_character, b'o');
+        assert_eq!(writer.buffer.chars[row][1].read().ascii_character, b'k');
+    })
+}
+
+#[test_case]
+fn test_input() {
+    // Make and break codes for "ab", then Enter, in scancode set 1
+    let scancodes = [0x1e, 0x9e, 0x30, 0xb0, 0x1c, 0x9c];
+    for scancode in scancodes {
+        keyboard::add_scancode(scancode);
+    }
+    assert_eq!(_input().as_str(), "ab");
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_input_clear_screen() {
+    println!("left on screen");
+    print!("> ");
+    // Ctrl+L, then "x" and Enter
+    let scancodes = [0x1d, 0x26, 0xa6, 0x9d, 0x2d, 0xad, 0x1c, 0x9c];
+    for scancode in scancodes {
+        keyboard::add_scancode(scancode);
+    }
+    assert_eq!(_input().as_str(), "x");
+    assert_row_eq(0, "> x");
+    for row in 1..BUFFER_HEIGHT {
+        assert_row_eq(row, "");
+    }
+}
+
+#[test_case]
+fn test_input_backspace() {
+    // "abc", backspace, "d", Enter
+    let scancodes = [
+        0x1e, 0x9e, 0x30, 0xb0, 0x2e, 0xae, 0x0e, 0x8e, 0x20, 0xa0, 0x1c, 0x9c,
+    ];
+    for scancode in scancodes {
+        keyboard::add_scancode(scancode);
+    }
+    assert_eq!(_input().as_str(), "abd");
+}
+
+#[test_case]
+fn test_input_home() {
+    // "abc", Home, Shift+"x", Enter
+    let scancodes = [
+        0x1e, 0x9e, 0x30, 0xb0, 0x2e, 0xae, 0xe0, 0x47, 0xe0, 0xc7, 0x2a, 0x2d, 0xad, 0xaa, 0x1c,
+        0x9c,
+    ];
+    for scancode in scancodes {
+        keyboard::add_scancode(scancode);
+    }
+    assert_eq!(_input().as_str(), "Xabc");
+}
+
+#[cfg(test)]
+fn assert_wrapped(word_wrap: bool, first_row_end: &[u8], second_row: &[u8]) {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        writer.set_word_wrap(word_wrap);
+        for _ in 0..76 {
+            writer.write_byte(b'x');
+        }
+        writer.write_string(" hello world");
+        writer.set_word_wrap(false);
+        let start = BUFFER_WIDTH - first_row_end.len();
+        for (i, &byte) in first_row_end.iter().enumerate() {
+            assert_eq!(
+                writer.buffer.chars[0][start + i].read().ascii_character,
+                byte
+            );
+        }
+        for (i, &byte) in second_row.iter().enumerate() {
+            assert_eq!(writer.buffer.chars[1][i].read().ascii_character, byte);
+        }
+    })
+}
+
+#[test_case]
+fn test_word_wrap_off() {
+    assert_wrapped(false, b"x hel", b"lo world");
+}
+
+#[test_case]
+fn test_word_wrap_on() {
+    assert_wrapped(true, b"x    ", b"hello world");
+}
+
+#[test_case]
+fn test_scrollback() {
+    use core::fmt::Write;
+    fn row_starts_with(writer: &Writer, row: usize, text: &[u8]) -> bool {
+        text.iter()
+            .enumerate()
+            .all(|(col, &byte)| writer.buffer.chars[row][col].read().ascii_character == byte)
+    }
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        for i in 0..40 {
+            writeln!(writer, "line {:02}", i).unwrap();
+        }
+        // 16 rows scrolled off, the last 10 of them are lines 06 to 15
+        writer.scroll_back(10);
+        assert!(row_starts_with(&writer, 0, b"line 06"));
+        assert!(row_starts_with(&writer, 10, b"line 16"));
+        writer.scroll_forward(10);
+        assert!(row_starts_with(&writer, 0, b"line 16"));
+        // New output snaps back to the live screen
+        writer.scroll_back(5);
+        write!(writer, "x").unwrap();
+        assert!(row_starts_with(&writer, 0, b"line 16"));
+        assert!(row_starts_with(&writer, BUFFER_HEIGHT - 1, b"x"));
+    })
+}
+
+#[test_case]
+fn test_status_bar() {
+    use core::fmt::Write;
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let color = ColorCode::new(Color::Black, Color::LightGray);
+        writer.set_status("status", color);
+        for i in 0..BUFFER_HEIGHT * 2 {
+            writeln!(writer, "line {}", i).unwrap();
+        }
+        assert!(writer.row_position < BUFFER_HEIGHT - 1);
+        for (col, &byte) in b"status ".iter().enumerate() {
+            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 1][col].read();
+            assert_eq!(screen_char.ascii_character, byte);
+            assert_eq!(screen_char.color_code, color);
+        }
+        writer.disable_status_bar();
+    })
+}
+
+#[test_case]
+fn test_draw_box() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.draw_box(1, 1, 3, 3, DEFAULT_COLOR_CODE);
+        let glyph = |row: usize, col: usize| writer.buffer.chars[row][col].read().ascii_character;
+        assert_eq!(glyph(1, 1), 0xC9);
+        assert_eq!(glyph(1, 3), 0xBB);
+        assert_eq!(glyph(3, 1), 0xC8);
+        assert_eq!(glyph(3, 3), 0xBC);
+        assert_eq!(glyph(1, 2), 0xCD);
+        assert_eq!(glyph(3, 2), 0xCD);
+        assert_eq!(glyph(2, 1), 0xBA);
+        assert_eq!(glyph(2, 3), 0xBA);
+    })
+}
+
+#[test_case]
+fn test_draw_box_off_screen() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let screen = writer.snapshot();
+        writer.draw_box(usize::MAX, 0, 3, 3, DEFAULT_COLOR_CODE);
+        writer.draw_box(0, usize::MAX, 3, 3, DEFAULT_COLOR_CODE);
+        writer.draw_box(BUFFER_HEIGHT, BUFFER_WIDTH, 3, 3, DEFAULT_COLOR_CODE);
+        assert!(writer.snapshot() == screen);
+    })
+}
+
+#[test_case]
+fn test_fill_rect_clips() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.fill_rect(
+            BUFFER_HEIGHT - 1,
+            BUFFER_WIDTH - 2,
+            5,
+            5,
+            b'#',
+            DEFAULT_COLOR_CODE,
+        );
+        writer.draw_box(
+            BUFFER_HEIGHT - 2,
+            BUFFER_WIDTH - 2,
+            10,
+            10,
+            DEFAULT_COLOR_CODE,
+        );
+        let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 1][BUFFER_WIDTH - 1].read();
+        assert_eq!(screen_char.ascii_character, b'#');
+        writer.clear_screen();
+    })
+}
+
+#[test_case]
+fn test_write_centered() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_row(0);
+        writer.write_centered(0, "abcd", DEFAULT_COLOR_CODE);
+        assert_eq!(writer.buffer.chars[0][37].read().ascii_character, b' ');
+        assert_eq!(writer.buffer.chars[0][38].read().ascii_character, b'a');
+        assert_eq!(writer.buffer.chars[0][41].read().ascii_character, b'd');
+        writer.clear_row(0);
+    })
+}
+
+#[test_case]
+fn test_write_right() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.write_right(0, "abcd", DEFAULT_COLOR_CODE);
+        assert_eq!(
+            writer.buffer.chars[0][BUFFER_WIDTH - 4]
+                .read()
+                .ascii_character,
+            b'a'
+        );
+        assert_eq!(
+            writer.buffer.chars[0][BUFFER_WIDTH - 1]
+                .read()
+                .ascii_character,
+            b'd'
+        );
+        // Too long for the row, starts at the left edge instead
+        let long = "x".repeat(BUFFER_WIDTH + 10);
+        writer.write_right(0, &long, DEFAULT_COLOR_CODE);
+        writer.write_centered(0, &long, DEFAULT_COLOR_CODE);
+        assert_eq!(writer.buffer.chars[0][0].read().ascii_character, b'x');
+        writer.clear_row(0);
+    })
+}
+
+#[test_case]
+fn test_draw_progress() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let position = (writer.row_position, writer.column_position);
+        for (fraction, expected) in [(0.0, 0), (0.5, 39), (1.0, 78), (2.0, 78)] {
+            writer.draw_progress(0, fraction, DEFAULT_COLOR_CODE);
+            let filled = (0..BUFFER_WIDTH)
+                .filter(|&col| writer.buffer.chars[0][col].read().ascii_character == b'#')
+                .count();
+            assert_eq!(filled, expected);
+        }
+        assert_eq!(writer.buffer.chars[0][0].read().ascii_character, b'[');
+        assert_eq!(
+            writer.buffer.chars[0][BUFFER_WIDTH - 1]
+                .read()
+                .ascii_character,
+            b']'
+        );
+        assert_eq!((writer.row_position, writer.column_position), position);
+        writer.clear_row(0);
+    })
+}
+
+#[test_case]
+fn test_blink_color_code() {
+    let color = ColorCode::new(Color::Yellow, Color::Blue);
+    assert_eq!(color.blink().0, 0x80 | color.0);
+    assert_eq!(color.blink().0 & 0x7f, color.0);
+}
+
+#[test_case]
+fn test_cp437_characters() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.write_string("\n");
+        let row = writer.row_position;
+        writer.write_char('\u{2591}');
+        writer.write_char('é');
+        writer.write_char('\u{2603}');
+        assert_eq!(writer.buffer.chars[row][0].read().ascii_character, 0xb0);
+        assert_eq!(writer.buffer.chars[row][1].read().ascii_character, 0x82);
+        assert_eq!(writer.buffer.chars[row][2].read().ascii_character, 0xfe);
+        assert_eq!(cp437_from_char('■'), Some(0xfe));
+    })
+}
+
+#[test_case]
+fn test_read_row_trimmed() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_row(0);
+        writer.write_at(0, 0, "known text", DEFAULT_COLOR_CODE);
+        assert_eq!(&writer.read_row_trimmed(0)[..], b"known text");
+        writer.clear_row(0);
+        assert!(writer.read_row_trimmed(0).is_empty());
+    })
+}
+
+#[test_case]
+fn test_flush() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_row(0);
+        writer.set_auto_flush(false);
+        writer.write_at(0, 0, "batched", DEFAULT_COLOR_CODE);
+        assert_eq!(writer.buffer.chars[0][0].read().ascii_character, b' ');
+        writer.flush();
+        assert_eq!(writer.buffer.chars[0][0].read().ascii_character, b'b');
+        writer.set_auto_flush(true);
+        writer.clear_row(0);
+    })
+}
+
+#[test_case]
+fn test_snapshot_restore() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.write_at(0, 0, "under the popup", DEFAULT_COLOR_CODE);
+        let snapshot = writer.snapshot();
+        let color = ColorCode::new(Color::Yellow, Color::Blue);
+        writer.fill_rect(0, 0, BUFFER_HEIGHT, BUFFER_WIDTH, b'#', color);
+        writer.restore(&snapshot);
+        for (row, chars) in snapshot.iter().enumerate() {
+            for (col, &screen_char) in chars.iter().enumerate() {
+                assert_eq!(writer.buffer.chars[row][col].read(), screen_char);
+            }
+        }
+        assert_eq!(&writer.read_row(0)[..15], b"under the popup");
+    })
+}
+
+#[test_case]
+fn test_print_centered_box() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let color = ColorCode::new(Color::LightCyan, Color::Black);
+        writer.print_centered_box(&["rust-os", "booting..."], color);
+        // 10 wide text makes a 14 by 6 box
+        let (top, left, bottom, right) = (9, 33, 14, 46);
+        assert_eq!(writer.cell(top, left), ScreenChar::new(0xC9, color));
+        assert_eq!(writer.cell(top, right), ScreenChar::new(0xBB, color));
+        assert_eq!(writer.cell(bottom, left), ScreenChar::new(0xC8, color));
+        assert_eq!(writer.cell(bottom, right), ScreenChar::new(0xBC, color));
+        assert_eq!(&writer.read_row(11)[36..43], b"rust-os");
+        assert_eq!(&writer.read_row(12)[35..45], b"booting...");
+        assert_eq!(writer.cell(11, 36).color_code, color);
+        // Too wide for the screen, so it's cut to fit inside the border
+        let wide = "x".repeat(100);
+        writer.print_centered_box(&[&wide], color);
+        assert_eq!(writer.cell(10, 0).ascii_character, 0xC9);
+        let row = writer.read_row(12);
+        assert_eq!(&row[2..78], &[b'x'; 76][..]);
+        assert_eq!((row[1], row[78], row[79]), (b' ', b' ', 0xBA));
+        writer.clear_screen();
+    })
+}
+
+#[test_case]
+fn test_wrap_text() {
+    let lines = wrap_text("one two three four", 9);
+    assert_eq!(&lines[..], ["one two", "three", "four"]);
+    // Too long for a line of its own, so it's split
+    let lines = wrap_text("abcdefghij xy", 4);
+    assert_eq!(&lines[..], ["abcd", "efgh", "ij", "xy"]);
+    let lines = wrap_text("first\n\nthird", 10);
+    assert_eq!(&lines[..], ["first", "", "third"]);
+}
+
+#[test_case]
+fn test_show_dialog() {
+    interrupts::without_interrupts(|| {
+        WRITER
+            .lock()
+            .write_at(12, 0, "behind the dialog", DEFAULT_COLOR_CODE)
+    });
+    let before = interrupts::without_interrupts(|| WRITER.lock().snapshot());
+    // Enter, then Esc
+    for scancode in [0x1c, 0x9c, 0x01, 0x81] {
+        keyboard::add_scancode(scancode);
+    }
+    let body = "This body is long enough that it has to be wrapped onto more lines";
+    assert_eq!(show_dialog("Notice", body), DialogResult::Ok);
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        assert!(writer.snapshot() == before);
+        for (row, chars) in before.iter().enumerate() {
+            for (col, &screen_char) in chars.iter().enumerate() {
+                assert_eq!(writer.buffer.chars[row][col].read(), screen_char);
+            }
+        }
+        assert!(!writer.input_mode);
+    });
+    assert_eq!(show_dialog("Notice", body), DialogResult::Cancel);
+    interrupts::without_interrupts(|| assert!(WRITER.lock().snapshot() == before));
+}
+
+#[test_case]
+fn test_console_switch_during_dialog() {
+    switch_console(0);
+    // Alt+F2 while the dialog is up, then Enter to close it. Switching from
+    // the decoder would spin on the WRITER lock the dialog used to hold
+    for scancode in [0x38, 0x3c, 0xbc, 0xb8, 0x1c, 0x9c] {
+        keyboard::add_scancode(scancode);
+    }
+    let before = interrupts::without_interrupts(|| WRITER.lock().snapshot());
+    assert_eq!(show_dialog("Notice", "switch"), DialogResult::Ok);
+    // The dialog is cleaned off console 0 before console 1 comes up
+    assert_eq!(active_console(), 1);
+    switch_console(0);
+    interrupts::without_interrupts(|| assert!(WRITER.lock().snapshot() == before));
+}
+
+#[test_case]
+fn test_console_switch_during_input() {
+    let key = DecodedKey::Unicode;
+    switch_console(1);
+    interrupts::without_interrupts(|| WRITER.lock().clear_screen());
+    switch_console(0);
+    // From the top, so Enter doesn't scroll the line away
+    interrupts::without_interrupts(|| WRITER.lock().clear_screen());
+    let mut editor = interrupts::without_interrupts(|| LineEditor::new(&mut WRITER.lock()));
+    let (row, col) = (editor.row, editor.col);
+    assert!(!editor.feed(key('a')));
+    request_console_switch(1);
+    assert!(!editor.feed(key('b')));
+    // Held back until the line is done, so the rest of it lands on console 0
+    assert_eq!(active_console(), 0);
+    assert!(editor.feed(key('\n')));
+    assert_eq!(active_console(), 1);
+    assert_eq!(editor.into_line().as_str(), "ab");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        assert!((0..writer.text_rows()).all(|row| writer.read_row_trimmed(row).is_empty()));
+    });
+    switch_console(0);
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        assert_eq!(&writer.read_row(row)[col..col + 2], b"ab");
+    });
+}
+
+#[test_case]
+fn test_draw_dialog_narrow() {
+    use alloc::boxed::Box;
+    // Below 8 columns there is no room for a dialog, so nothing is drawn,
+    // and at 8 and 9 it has to fit without anything underflowing
+    for width in 2..10 {
+        let buffer: &'static mut Buffer = Box::leak(Box::new(unsafe { core::mem::zeroed() }));
+        let mut writer = Box::new(Writer::with_size(buffer, width, 6));
+        writer.draw_dialog("Title", "body text");
+        let drawn = (0..6).any(|row| !writer.read_row_trimmed(row).is_empty());
+        assert_eq!(drawn, width >= 8);
+    }
+}
+
+#[test_case]
+fn test_small_buffer_scroll() {
+    use alloc::boxed::Box;
+    use core::fmt::Write;
+    // A zeroed ScreenChar is a valid (black on black NUL) cell
+    let buffer: &'static mut Buffer = Box::leak(Box::new(unsafe { core::mem::zeroed() }));
+    let mut writer = Box::new(Writer::with_size(buffer, 4, 3));
+    write!(writer, "abcdefgh\nij\nkl").unwrap();
+    // abcd, efgh, ij and kl were written but only the last three rows fit
+    assert_eq!(&writer.read_row_trimmed(0)[..], b"efgh");
+    assert_eq!(&writer.read_row_trimmed(1)[..], b"ij");
+    assert_eq!(&writer.read_row_trimmed(2)[..], b"kl");
+    assert_eq!((writer.row_position, writer.column_position), (2, 2));
+    assert_eq!(
+        writer.scrollback.get(writer.scrollback.len() - 1)[0].ascii_character,
+        b'a'
+    );
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_print_while_locked() {
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let dropped = dropped_messages();
+        // Would spin forever if _print waited for the lock
+        print!("dropped");
+        cprint!(Color::Red, "dropped");
+        assert_eq!(dropped_messages(), dropped + 2);
+        drop(writer);
+    })
+}
+
+#[test_case]
+fn test_clear_while_locked() {
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        let dropped = dropped_messages();
+        // Would spin forever if _clear waited for the lock
+        crate::clear!();
+        assert_eq!(dropped_messages(), dropped + 1);
+        drop(writer);
+    })
+}
+
+#[test_case]
+fn test_clear_during_input() {
+    use core::fmt::Write;
+    interrupts::without_interrupts(|| {
+        let row = {
+            let mut writer = WRITER.lock();
+            writeln!(writer).unwrap();
+            write!(writer, "typing").unwrap();
+            writer.input_mode = true;
+            writer.row_position
+        };
+        crate::clear!();
+        let mut writer = WRITER.lock();
+        // The line being read is left alone until input ends
+        assert_eq!(&writer.read_row(row)[..6], b"typing");
+        writer.end_input();
+        assert_eq!((writer.row_position, writer.column_position), (0, 0));
+        assert!(writer.read_row_trimmed(row).is_empty());
+    })
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_switch_console() {
+    switch_console(0);
+    println!();
+    print!("A");
+    let row = interrupts::without_interrupts(|| WRITER.lock().row_position);
+    switch_console(1);
+    assert_eq!(active_console(), 1);
+    print!("B");
+    assert_row_eq(0, "B");
+    switch_console(0);
+    assert_row_eq(row, "A");
+    interrupts::without_interrupts(|| {
+        let writer = WRITER.lock();
+        assert_eq!((writer.row_position, writer.column_position), (row, 1));
+    });
+    switch_console(1);
+    assert_row_eq(0, "B");
+    interrupts::without_interrupts(|| WRITER.lock().clear_screen());
+    switch_console(0);
+    assert_eq!(active_console(), 0);
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_print_locks_once() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.input_mode = true;
+        let dropped = dropped_messages();
+        let pending = writer.pending_output.len();
+        // One failed try_lock per print, and nothing queued, so no other
+        // access to the writer was made outside that one attempt
+        print!("dropped");
+        assert_eq!(dropped_messages(), dropped + 1);
+        assert_eq!(writer.pending_output.len(), pending);
+        writer.input_mode = false;
+    })
+}
+
+#[test_case]
+fn test_write_past_last_row() {
+    use core::fmt::Write;
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_row(BUFFER_HEIGHT - 1);
+        writer.write_at(BUFFER_HEIGHT - 1, 0, "old bottom", DEFAULT_COLOR_CODE);
+        writer.row_position = BUFFER_HEIGHT;
+        writer.column_position = 0;
+        write!(writer, "x").unwrap();
+        assert_eq!(writer.row_position, BUFFER_HEIGHT - 1);
+        assert_eq!(
+            &writer.read_row_trimmed(BUFFER_HEIGHT - 2)[..],
+            b"old bottom"
+        );
+        assert_eq!(&writer.read_row_trimmed(BUFFER_HEIGHT - 1)[..], b"x");
+    })
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_print_during_input() {
+    let mut editor = interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_screen();
+        LineEditor::new(&mut writer)
+    });
+    let key = |editor: &mut LineEditor, c| {
+        interrupts::without_interrupts(|| {
+            editor.handle_key(&mut WRITER.lock(), DecodedKey::Unicode(c))
+        })
+    };
+    key(&mut editor, 'a');
+    println!("background");
+    key(&mut editor, 'b');
+    assert!(key(&mut editor, '\n'));
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.end_input();
+        assert_eq!(&writer.read_row_trimmed(0)[..], b"ab");
+        assert_eq!(&writer.read_row_trimmed(1)[..], b"background");
+        assert!(!writer.input_mode);
+    });
+    assert_eq!(editor.into_line().as_str(), "ab");
+}
+
+#[test_case]
+fn test_page_up() {
+    use core::fmt::Write;
+    let mut editor = interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        for i in 0..50 {
+            writeln!(writer, "page line {}", i).unwrap();
+        }
+        LineEditor::new(&mut writer)
+    });
+    let top = interrupts::without_interrupts(|| WRITER.lock().read_row(0));
+    // PageUp make and break codes
+    for scancode in [0xe0, 0x49, 0xe0, 0xc9] {
+        keyboard::add_scancode(scancode);
+    }
+    while let Some(key) = keyboard::try_read_key() {
+        interrupts::without_interrupts(|| editor.handle_key(&mut WRITER.lock(), key));
+    }
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        assert_ne!(writer.read_row(0), top);
+        // Any other key snaps back to the live screen, with the cursor where
+        // it was
+        let position = (writer.row_position, writer.column_position);
+        editor.handle_key(&mut writer, DecodedKey::RawKey(KeyCode::ArrowLeft));
+        assert_eq!(writer.read_row(0), top);
+        assert_eq!((writer.row_position, writer.column_position), position);
+        editor.handle_key(&mut writer, DecodedKey::Unicode('\n'));
+        writer.end_input();
+    });
+}
+
+#[test_case]
+fn test_nearest_from_rgb() {
+    assert_eq!(Color::nearest_from_rgb(255, 0, 0), Color::Red);
+    assert_eq!(Color::nearest_from_rgb(0, 0, 0), Color::Black);
+    assert_eq!(Color::nearest_from_rgb(128, 128, 128), Color::LightGray);
+    assert_eq!(Color::nearest_from_rgb(255, 255, 255), Color::White);
+    assert_eq!(Color::nearest_from_rgb(240, 230, 90), Color::Yellow);
+}
+
+#[test_case]
+fn test_shade() {
+    assert_eq!(shade(0), (b' ', Color::Black));
+    assert_eq!(shade(128), (0xB2, Color::LightGray));
+    assert_eq!(shade(255), (0xDB, Color::White));
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.write_shaded(0, 0, 255);
+        let screen_char = writer.buffer.chars[0][0].read();
+        assert_eq!(screen_char.ascii_character, 0xDB);
+        assert_eq!(
+            screen_char.color_code,
+            ColorCode::new(Color::White, Color::Black)
+        );
+    })
+}
+
+#[test_case]
+fn test_write_bytes() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.write_string("\n");
+        let row = writer.row_position;
+        // Not valid UTF-8, 0xb2 on its own is a continuation byte
+        writer.write_bytes(b"ab\x08\xb2\x01");
+        let text = writer.read_row(row);
+        assert_eq!(&text[..3], b"a\xb2\xfe");
+    })
+}
+
+#[cfg(test)]
+fn edit_line(keys: &[DecodedKey]) -> InputLine {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let mut editor = LineEditor::new(&mut writer);
+        for &key in keys {
+            editor.handle_key(&mut writer, key);
+        }
+        editor.handle_key(&mut writer, DecodedKey::Unicode('\n'));
+        writer.end_input();
+        editor.into_line()
+    })
+}
+
+#[cfg(test)]
+const TYPE_INTO_ABC: [DecodedKey; 6] = [
+    DecodedKey::Unicode('a'),
+    DecodedKey::Unicode('b'),
+    DecodedKey::Unicode('c'),
+    DecodedKey::RawKey(KeyCode::ArrowLeft),
+    DecodedKey::RawKey(KeyCode::ArrowLeft),
+    DecodedKey::Unicode('x'),
+];
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_ctrl_c() {
+    println!();
+    let abc = [
+        DecodedKey::Unicode('a'),
+        DecodedKey::Unicode('b'),
+        DecodedKey::Unicode('c'),
+        DecodedKey::Unicode('\u{3}'),
+    ];
+    assert_eq!(edit_line(&abc).as_str(), "");
+    let row = interrupts::without_interrupts(|| WRITER.lock().row_position);
+    assert_row_eq(row - 1, "abc^C");
+}
+
+#[cfg(feature = "vga_text")]
+#[test_case]
+fn test_ctrl_u() {
+    println!();
+    let cleared = [
+        DecodedKey::Unicode('a'),
+        DecodedKey::Unicode('b'),
+        DecodedKey::Unicode('c'),
+        DecodedKey::Unicode('\u{15}'),
+    ];
+    assert_eq!(edit_line(&cleared).as_str(), "");
+    let row = interrupts::without_interrupts(|| WRITER.lock().row_position);
+    assert_row_eq(row - 1, "");
+    // Typing carries on from the start of the cleared line
+    let mut keys = [DecodedKey::Unicode('d'); 5];
+    keys[..4].copy_from_slice(&cleared);
+    assert_eq!(edit_line(&keys).as_str(), "d");
+}
+
+#[test_case]
+fn test_insert_mode() {
+    assert_eq!(edit_line(&TYPE_INTO_ABC).as_str(), "axbc");
+}
+
+#[test_case]
+fn test_overwrite_mode() {
+    let mut keys = [DecodedKey::RawKey(KeyCode::Insert); 10];
+    keys[1..7].copy_from_slice(&TYPE_INTO_ABC);
+    // Overwriting runs past the end of the line, from there it appends
+    keys[7] = DecodedKey::Unicode('y');
+    keys[8] = DecodedKey::Unicode('z');
+    // Back to insert mode for the rest of the tests
+    keys[9] = DecodedKey::RawKey(KeyCode::Insert);
+    assert_eq!(edit_line(&keys).as_str(), "axyz");
+}
+
+#[test_case]
+fn test_color_guard() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let old_color = writer.color();
+        {
+            let mut writer = writer.with_color(Color::Cyan, Color::Blue);
+            writer.write_string("guarded");
+            assert_eq!(writer.color(), ColorCode::new(Color::Cyan, Color::Blue));
+        }
+        assert_eq!(writer.color(), old_color);
+    })
+}
+
+#[test_case]
+fn test_write_block() {
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        writer.clear_row(5);
+        writer.clear_row(6);
+        let color = ColorCode::new(Color::Pink, Color::Black);
+        let mut cells = [ScreenChar::blank(color); 10];
+        for (i, cell) in cells.iter_mut().enumerate() {
+            cell.ascii_character = b'0' + i as u8;
+        }
+        writer.write_block(&cells, 5, BUFFER_WIDTH - 5);
+        assert_eq!(&writer.read_row(5)[BUFFER_WIDTH - 6..], b" 01234");
+        assert_eq!(&writer.read_row_trimmed(6)[..], b"56789");
+        assert_eq!(writer.buffer.chars[5][BUFFER_WIDTH - 5].read(), cells[0]);
+        assert_eq!(writer.buffer.chars[6][4].read(), cells[9]);
+        // Running off the bottom drops the rest instead of wrapping around
+        let last_row = BUFFER_HEIGHT - 1;
+        let top = writer.read_cells(0);
+        writer.write_block(&cells, last_row, BUFFER_WIDTH - 2);
+        assert_eq!(writer.cell(last_row, BUFFER_WIDTH - 1), cells[1]);
+        assert!(writer.read_cells(0) == top);
+        writer.clear_row(5);
+        writer.clear_row(6);
+        writer.clear_row(last_row);
+    })
+}
+
+#[test_case]
+fn test_screen_char_blank() {
+    let color = ColorCode::new(Color::Green, Color::Blue);
+    let blank = ScreenChar::blank(color);
+    assert_eq!(blank.ascii_character, b' ');
+    assert_eq!(blank.color_code, color);
+    assert_eq!(ScreenChar::new(b' ', color), blank);
+    assert_eq!(BLANK_CHAR, ScreenChar::blank(DEFAULT_COLOR_CODE));
+}
+
+#[test_case]
+fn test_scrollback_limit() {
+    let mut scrollback = alloc::boxed::Box::new(Scrollback::new());
+    scrollback.set_limit(3);
+    for i in 0..5 {
+        let screen_char = ScreenChar::new(b'0' + i, DEFAULT_COLOR_CODE);
+        scrollback.push([screen_char; BUFFER_WIDTH]);
+    }
+    assert_eq!(scrollback.len(), 3);
+    assert_eq!(scrollback.get(0)[0].ascii_character, b'2');
+    assert_eq!(scrollback.get(2)[0].ascii_character, b'4');
+    scrollback.set_limit(1);
+    assert_eq!(scrollback.len(), 1);
+    assert_eq!(scrollback.get(0)[0].ascii_character, b'4');
+}
+
+#[cfg(feature = "alloc_scrollback")]
+#[test_case]
+fn test_scrollback_reserved() {
+    let mut scrollback = alloc::boxed::Box::new(Scrollback::new());
+    // Nothing is kept, or allocated, before init
+    scrollback.push([BLANK_CHAR; BUFFER_WIDTH]);
+    assert_eq!(scrollback.len(), 0);
+    scrollback.init();
+    let capacity = scrollback.rows.as_ref().unwrap().capacity();
+    assert!(capacity >= SCROLLBACK_LINES);
+    for _ in 0..SCROLLBACK_LINES * 2 {
+        scrollback.push([BLANK_CHAR; BUFFER_WIDTH]);
+    }
+    assert_eq!(scrollback.len(), SCROLLBACK_LINES);
+    assert_eq!(scrollback.rows.as_ref().unwrap().capacity(), capacity);
+}
+
+#[test_case]
+fn test_history_recall() {
+    let mut history = History::new();
+    history.push("first");
+    history.push("");
+    history.push("second");
+    history.push("second");
+    assert_eq!(history.len(), 2);
+    let up = DecodedKey::RawKey(KeyCode::ArrowUp);
+    let down = DecodedKey::RawKey(KeyCode::ArrowDown);
+    let recalled = |keys: &[DecodedKey]| {
+        interrupts::without_interrupts(|| {
+            let mut writer = WRITER.lock();
+            let mut editor = LineEditor::new(&mut writer).with_history(&history);
+            for &key in keys {
+                editor.handle_key(&mut writer, key);
+            }
+            editor.handle_key(&mut writer, DecodedKey::Unicode('\n'));
+            writer.end_input();
+            editor.into_line()
+        })
+    };
+    assert_eq!(recalled(&[up]).as_str(), "second");
+    assert_eq!(recalled(&[up, up]).as_str(), "first");
+    // Going past the oldest line stays on it
+    assert_eq!(recalled(&[up, up, up]).as_str(), "first");
+    assert_eq!(recalled(&[up, up, down]).as_str(), "second");
+    // Coming all the way back down gives the typed line back
+    let typed = [DecodedKey::Unicode('x'), up, down];
+    assert_eq!(recalled(&typed).as_str(), "x");
 }
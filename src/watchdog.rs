@@ -0,0 +1,55 @@
+use crate::interrupts::ticks;
+use crate::pit;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// The tick count the watchdog fires at, u64::MAX while disarmed. The timer
+// handler only ever compares against this
+static DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+// How far pet pushes the deadline out, in ticks
+static TIMEOUT: AtomicU64 = AtomicU64::new(0);
+
+// Panics from the timer interrupt unless pet is called at least every `ms`.
+// The timeout is rounded up to whole ticks, so at the BIOS rate it can be
+// up to ~55ms late
+pub fn arm(ms: u64) {
+    let timeout = pit::ms_to_ticks(ms).max(1);
+    TIMEOUT.store(timeout, Ordering::Relaxed);
+    DEADLINE.store(ticks() + timeout, Ordering::Relaxed);
+}
+
+pub fn pet() {
+    if is_armed() {
+        DEADLINE.store(ticks() + TIMEOUT.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+pub fn disarm() {
+    DEADLINE.store(u64::MAX, Ordering::Relaxed);
+}
+
+pub fn is_armed() -> bool {
+    DEADLINE.load(Ordering::Relaxed) != u64::MAX
+}
+
+// Called by the timer handler on every tick
+pub fn check(now: u64) {
+    if now > DEADLINE.load(Ordering::Relaxed) {
+        // Only fire once, the panic handler may well enable interrupts again
+        disarm();
+        panic!("watchdog timeout");
+    }
+}
+
+#[test_case]
+fn test_pet() {
+    arm(100);
+    for _ in 0..10 {
+        pit::sleep_ms(20);
+        pet();
+    }
+    assert!(is_armed());
+    disarm();
+    assert!(!is_armed());
+    // Deadlines far in the past do nothing once disarmed
+    check(u64::MAX - 1);
+}
@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::allocator::{self, HEAP_SIZE};
+use rust_os::memory::{self, BootInfoFrameAllocator};
+use rust_os::vga_buffer::WRITER;
+use rust_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::VirtAddr;
+
+const MESSAGE: &[u8] = b"allocation error: size";
+
+entry_point!(main);
+
+// Asks for more than the whole heap. The allocation error handler should
+// print the layout and then panic, which is the success path here
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("alloc_error::exhaust_heap...\t");
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    let huge: Vec<u8> = Vec::with_capacity(HEAP_SIZE * 2);
+    serial_println!("[failed]");
+    serial_println!(
+        "Allocated {} bytes on a {} byte heap",
+        huge.capacity(),
+        HEAP_SIZE
+    );
+    exit_qemu(QemuExitCode::Failed);
+    rust_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    let printed = match WRITER.try_lock() {
+        Some(writer) => {
+            let row = writer.read_row(writer.row_position - 1);
+            row.starts_with(MESSAGE)
+        }
+        None => false,
+    };
+    if printed {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("The allocation error message was not printed");
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_os::hlt_loop();
+}
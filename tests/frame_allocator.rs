@@ -0,0 +1,47 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_os::memory::BootInfoFrameAllocator;
+use spin::Mutex;
+use x86_64::structures::paging::FrameAllocator;
+
+// Test cases can't take arguments, so the allocator is handed over here
+static FRAME_ALLOCATOR: Mutex<Option<BootInfoFrameAllocator>> = Mutex::new(None);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_os::init();
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    test_main();
+    rust_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn distinct_aligned_frames() {
+    let mut frame_allocator = FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+    let mut frames = [0u64; 16];
+    for frame in frames.iter_mut() {
+        *frame = frame_allocator
+            .allocate_frame()
+            .expect("out of frames")
+            .start_address()
+            .as_u64();
+    }
+    for (i, frame) in frames.iter().enumerate() {
+        assert_eq!(frame % 4096, 0);
+        assert!(!frames[i + 1..].contains(frame));
+    }
+}
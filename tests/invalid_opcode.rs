@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use rust_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+// Address of the ud2 instruction, stored by the asm right before it runs
+static UD2_ADDRESS: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.invalid_opcode
+            .set_handler_fn(test_invalid_opcode_handler);
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_invalid_opcode_handler(stack_frame: InterruptStackFrame) {
+    let rip = rust_os::interrupts::print_invalid_opcode(&stack_frame);
+    let expected = UD2_ADDRESS.load(Ordering::SeqCst);
+    if rip.as_u64() == expected {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("RIP was {:?}, ud2 is at {:#x}", rip, expected);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_os::hlt_loop();
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("invalid_opcode::ud2...\t");
+    rust_os::gdt::init();
+    TEST_IDT.load();
+    // AtomicU64 has the same layout as u64
+    let slot = &UD2_ADDRESS as *const AtomicU64 as *mut u64;
+    unsafe {
+        asm!(
+            "lea {address}, [rip + 2f]",
+            "mov [{slot}], {address}",
+            "2:",
+            "ud2",
+            address = out(reg) _,
+            slot = in(reg) slot,
+        );
+    }
+    serial_println!("[failed]");
+    serial_println!("Execution continued after ud2");
+    exit_qemu(QemuExitCode::Failed);
+    rust_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
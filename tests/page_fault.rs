@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use rust_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+const UNMAPPED: u64 = 0x_dead_beef_0000;
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+// Goes through the kernel's own reporting, then checks what it saw instead
+// of halting
+extern "x86-interrupt" fn test_page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let address = rust_os::interrupts::print_page_fault(&stack_frame, error_code);
+    if address.as_u64() == UNMAPPED {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("CR2 was {:?}", address);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_os::hlt_loop();
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("page_fault::read_unmapped...\t");
+    rust_os::gdt::init();
+    TEST_IDT.load();
+    unsafe {
+        core::ptr::read_volatile(UNMAPPED as *const u8);
+    }
+    serial_println!("[failed]");
+    serial_println!("Reading an unmapped address did not fault");
+    exit_qemu(QemuExitCode::Failed);
+    rust_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
@@ -0,0 +1,44 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use rust_os::vga_buffer::WRITER;
+use rust_os::{exit_qemu, println, serial_print, serial_println, QemuExitCode};
+
+const MESSAGE: &str = "panic with the writer locked";
+
+// Panics while WRITER is locked. The panic handler has to force the lock
+// open to get its message on screen, like the kernel's own handler does
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("panic_unlock::panic_while_locked...\t");
+    let writer = WRITER.lock();
+    core::mem::forget(writer);
+    panic!("{}", MESSAGE);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    x86_64::instructions::interrupts::disable();
+    unsafe {
+        rust_os::vga_buffer::force_unlock();
+    }
+    println!();
+    println!("{}", info);
+    let printed = match WRITER.try_lock() {
+        Some(writer) => {
+            let row = writer.read_row(writer.row_position - 1);
+            row.windows(MESSAGE.len())
+                .any(|window| window == MESSAGE.as_bytes())
+        }
+        None => false,
+    };
+    if printed {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_os::hlt_loop();
+}
@@ -0,0 +1,24 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use rust_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+// Actually powering off ends QEMU with status 0, which bootimage counts as
+// a failure. Going through power_off with the isa-debug-exit device from
+// test-args instead gives the success code, as long as the port write
+// reaches the device. If it doesn't, power_off halts and the test times out
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("shutdown::power_off...\t");
+    serial_println!("[ok]");
+    rust_os::power::power_off(&[(0xf4, QemuExitCode::Success as u16)]);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    serial_println!("[failed]");
+    serial_println!("Error: {}", info);
+    exit_qemu(QemuExitCode::Failed);
+    rust_os::hlt_loop();
+}
@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use rust_os::memory::{self, BootInfoFrameAllocator};
+use rust_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::VirtAddr;
+
+const STACK_PAGES: usize = 4;
+
+// Lowest address of the guard page under the test stack
+static GUARD_PAGE: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(rust_os::gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
+
+// There is no page fault handler, so the fault escalates to a double fault.
+// CR2 still holds the address that page faulted
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    let fault = Cr2::read().as_u64();
+    let guard = GUARD_PAGE.load(Ordering::Relaxed);
+    if (guard..guard + 4096).contains(&fault) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("Fault at {:#x}, guard page at {:#x}", fault, guard);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_os::hlt_loop();
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("stack_guard::overflow_hits_guard...\t");
+    rust_os::gdt::init();
+    TEST_IDT.load();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let top = memory::map_stack_with_guard(&mut mapper, &mut frame_allocator, STACK_PAGES)
+        .expect("stack mapping failed");
+    let guard = top.as_u64() - (STACK_PAGES as u64 + 1) * 4096;
+    GUARD_PAGE.store(guard, Ordering::Relaxed);
+
+    unsafe {
+        asm!(
+            "mov rsp, {stack}",
+            "call {entry}",
+            stack = in(reg) top.as_u64(),
+            entry = in(reg) overflow as usize,
+            options(noreturn),
+        );
+    }
+}
+
+extern "C" fn overflow() -> ! {
+    stack_overflow();
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    volatile::Volatile::new(0).read();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
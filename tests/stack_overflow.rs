@@ -24,7 +24,7 @@ extern "x86-interrupt" fn test_double_fault_handler(
 ) -> ! {
     serial_println!("[ok]");
     exit_qemu(rust_os::QemuExitCode::Success);
-    loop {}
+    rust_os::hlt_loop();
 }
 
 pub fn init_test_idt() {
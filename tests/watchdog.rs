@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use rust_os::pit;
+use rust_os::{exit_qemu, serial_print, serial_println, watchdog, QemuExitCode};
+
+// Petting inside the window has to keep the watchdog quiet, then the
+// timeout panic from the timer handler is the success path
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("watchdog::expire...\t");
+    rust_os::init();
+    watchdog::arm(200);
+    for _ in 0..10 {
+        pit::sleep_ms(50);
+        watchdog::pet();
+    }
+    // Never petted again from here
+    pit::sleep_ms(2000);
+    serial_println!("[failed]");
+    serial_println!("The watchdog never fired");
+    exit_qemu(QemuExitCode::Failed);
+    rust_os::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = heapless::String::<128>::new();
+    let _ = write!(message, "{}", info);
+    if message.contains("watchdog timeout") {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("{}", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_os::hlt_loop();
+}